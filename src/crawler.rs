@@ -0,0 +1,270 @@
+use std::fs;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{Future, StreamExt};
+use log::error;
+use serde_json::Value;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::errors::{ConfigError, CrawlError};
+use crate::logging::LogSettings;
+use crate::metrics;
+use crate::progress::{self, ProgressView};
+use crate::replay::ReplaySource;
+use crate::scraper_unit::ScraperUnit;
+use crate::sink::{CallbackSink, CrawlResult, ResultSink};
+use crate::stats::StatsSnapshot;
+
+// What `Crawler::run` returns once every job has finished
+#[derive(Debug, Clone)]
+pub struct CrawlSummary {
+    pub elapsed: Duration,
+    pub stats: StatsSnapshot,
+}
+
+// Runs one ScraperUnit config, e.g.
+// `Crawler::from_file("crawl.json")?.max_depth(2).run().await`
+#[derive(Debug)]
+pub struct Crawler {
+    unit: ScraperUnit,
+}
+
+impl Crawler {
+    pub fn from_json(raw: &str) -> Result<Self, ConfigError> {
+        Ok(Crawler {
+            unit: serde_json::from_str(raw)?,
+        })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        Crawler::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_value(config: Value) -> Result<Self, ConfigError> {
+        Ok(Crawler {
+            unit: serde_json::from_value(config)?,
+        })
+    }
+
+    pub fn concurrency(mut self, jobs: usize) -> Self {
+        self.unit.limits_mut().set_concurrency(jobs);
+        self
+    }
+
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.unit.limits_mut().set_max_depth(depth);
+        self
+    }
+
+    pub fn max_pages(mut self, pages: u64) -> Self {
+        self.unit.limits_mut().set_max_pages(pages);
+        self
+    }
+
+    // Serve responses from an archive instead of the network, overriding the config's `replay`
+    pub fn replay(mut self, source: ReplaySource) -> Self {
+        self.unit.set_replay(source);
+        self
+    }
+
+    // Every result of every Process step is handed to the sink
+    pub fn sink(mut self, sink: impl ResultSink + 'static) -> Self {
+        self.unit.sinks_mut().push(Arc::new(sink));
+        self
+    }
+
+    // Receives what `{"Store":{"Channel":{"name":...}}}` steps push; the crawl waits while the channel is full
+    pub fn channel(mut self, name: &str, sender: Sender<CrawlResult>) -> Self {
+        self.unit.sinks_mut().add_channel(name, Arc::new(sender));
+        self
+    }
+
+    // Called for what `{"Store":{"Callback":{"name":...}}}` steps push; the crawl waits for the returned future
+    pub fn callback<F, Fut>(mut self, name: &str, callback: F) -> Self
+    where
+        F: Fn(CrawlResult) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.unit.sinks_mut().add_callback(name, Arc::new(CallbackSink::new(callback)));
+        self
+    }
+
+    pub fn log_settings(&self) -> &LogSettings {
+        self.unit.log_settings()
+    }
+
+    // The live progress view takes over the terminal while the crawl runs
+    pub fn shows_live_progress(&self) -> bool {
+        self.unit.progress_settings().is_some() && progress::is_live()
+    }
+
+    // Results arrive on the stream as they are extracted; at most `buffer` of them wait
+    // for the consumer before the crawl pauses. The stream ends with the crawl
+    pub fn results(
+        self,
+        buffer: usize,
+    ) -> (ReceiverStream<CrawlResult>, JoinHandle<Result<CrawlSummary, CrawlError>>) {
+        let (result_tx, result_rx) = channel(buffer.max(1));
+        let crawl = tokio::spawn(self.sink(result_tx).run());
+        (result_rx.into(), crawl)
+    }
+
+    // Fails before any request when the replay archive or the state store can't be opened
+    pub async fn run(self) -> Result<CrawlSummary, CrawlError> {
+        let unit = self.unit.open().await?;
+        let stats = unit.stats();
+        let report_path = unit.report_path().cloned();
+        let concurrency = unit.limits().concurrency();
+        let metrics_server = unit
+            .metrics_settings()
+            .map(|metrics_settings| tokio::spawn(metrics::serve(metrics_settings.clone(), stats.clone())));
+        let progress_view = unit
+            .progress_settings()
+            .map(|progress_settings| ProgressView::start(progress_settings, stats.clone()));
+
+        let (tx, rx) = channel::<Pin<Box<dyn Future<Output = ()> + Send>>>(concurrency);
+        tokio::spawn(async move {
+            if tx.send(Box::pin(unit.run(tx.clone()))).await.is_err() {
+                error!("Failed to push initial Scaper Unit to the channel.");
+            }
+        });
+        let stream: ReceiverStream<_> = rx.into();
+        stream.for_each_concurrent(concurrency, |fut| fut).await;
+
+        // The channel closes once every queued job has finished
+        if let Some(progress_view) = progress_view {
+            progress_view.finish();
+        }
+        if let Some(metrics_server) = metrics_server {
+            metrics_server.abort();
+        }
+        stats.log_summary();
+        if let Some(report_path) = report_path {
+            if let Err(e) = stats.write_report(&report_path) {
+                error!("Failed to write report to {:?}: {}", report_path, e);
+            }
+        }
+        Ok(CrawlSummary {
+            elapsed: stats.elapsed(),
+            stats: stats.snapshot(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::parser::ProcessingResultUnit;
+    use crate::test_server::{MockResponse, MockServer};
+
+    fn html(body: &str) -> MockResponse {
+        MockResponse::new(200)
+            .header("Content-Type", "text/html")
+            .body(format!("<html><body>{}</body></html>", body))
+    }
+
+    async fn site() -> MockServer {
+        let base = Arc::new(std::sync::OnceLock::<String>::new());
+        let links_base = base.clone();
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/" => {
+                let base = links_base.get().unwrap();
+                html(&format!(
+                    r#"<a href="{0}/a">A</a><a href="{0}/b">B</a><a href="{0}/c">C</a>"#,
+                    base
+                ))
+            }
+            "/a" | "/b" | "/c" => html(&format!("<h1>Page {}</h1>", &request.path[1..])),
+            _ => MockResponse::new(404),
+        })
+        .await;
+        base.set(server.url.clone()).unwrap();
+        server
+    }
+
+    fn config(url: &str) -> String {
+        json!({
+            "urls": [format!("{}/", url)],
+            "scraper": {"targets": {"Text": [{"Process": {
+                "type": "Html", "selector": "a", "capture_elements": "All",
+                "selector_target": {"Attr": "href"}, "proc_result": "URL",
+                "next_steps": [{"Scrape": {"targets": {"Text": [{"Process": {
+                    "type": "Html", "selector": "h1", "capture_elements": "All",
+                    "selector_target": "Text", "proc_result": "Str", "next_steps": []
+                }}]}}}]
+            }}]}}
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn results_stream_and_summary_cover_the_whole_crawl() {
+        let server = site().await;
+        let crawler = Crawler::from_json(&config(&server.url)).unwrap().max_pages(3);
+        let (results, crawl) = crawler.results(1);
+        let results = results.collect::<Vec<CrawlResult>>().await;
+        let summary = crawl.await.unwrap().unwrap();
+
+        // The start page and the first two links it leads to
+        assert_eq!(summary.stats.pages_done, 3);
+        assert_eq!(summary.stats.requests, 3);
+        assert_eq!(summary.stats.status_codes.get(&200), Some(&3));
+        assert_eq!(server.requests().len(), 3);
+
+        let mut urls = vec![];
+        let mut titles = vec![];
+        for result in &results {
+            match &result.result {
+                ProcessingResultUnit::URL(url) => {
+                    assert_eq!(result.provenance.step, "/scraper/targets/Text/0");
+                    urls.push(url.path().to_string());
+                }
+                ProcessingResultUnit::Str(title) => {
+                    assert_eq!(result.provenance.step, "/scraper/targets/Text/0/Process/next_steps/0/Scrape/targets/Text/0");
+                    assert_eq!(result.provenance.parent_urls, vec![format!("{}/", server.url)]);
+                    titles.push(title.clone());
+                }
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+        urls.sort();
+        titles.sort();
+        assert_eq!(urls, vec!["/a", "/b", "/c"]);
+        assert_eq!(titles.len(), 2);
+        assert!(titles.iter().all(|title| title.starts_with("Page ")));
+    }
+
+    #[tokio::test]
+    async fn depth_limit_keeps_the_crawl_on_the_start_page() {
+        let server = site().await;
+        let summary = Crawler::from_value(serde_json::from_str(&config(&server.url)).unwrap())
+            .unwrap()
+            .max_depth(0)
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(summary.stats.pages_done, 1);
+        assert_eq!(summary.stats.max_depth, 0);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unreadable_replay_archive_fails_before_any_request() {
+        let server = site().await;
+        let missing = std::env::temp_dir().join(format!("crawler-missing-{}", nanoid::nanoid!(8)));
+        let crawled = Crawler::from_json(&config(&server.url))
+            .unwrap()
+            .replay(ReplaySource::Warc(missing))
+            .run()
+            .await;
+        assert!(matches!(crawled, Err(CrawlError::ReplayArchive(ReplaySource::Warc(_), _))));
+        assert!(server.requests().is_empty());
+    }
+}
@@ -3,4 +3,4 @@ use std::pin::Pin;
 
 use tokio::sync::mpsc::Sender;
 
-pub type PinnedFutureSender = Sender<Pin<Box<(dyn Future<Output = ()> + Send)>>>;
\ No newline at end of file
+pub type PinnedFutureSender = Sender<Pin<Box<dyn Future<Output = ()> + Send>>>;
\ No newline at end of file
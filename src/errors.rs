@@ -1,8 +1,11 @@
 use std::error::Error;
 use std::io::Error as _IOError;
+use std::path::PathBuf;
 
 use regex::Error as RegexError;
 
+use crate::replay::ReplaySource;
+
 pub enum ProcessorError {
     HtmlParserBuildError(String),
     NothingToCaptureError,
@@ -12,6 +15,10 @@ pub enum ProcessorError {
     IOError(_IOError),
     UrlParseError(url::ParseError),
     AuthenticationError(String),
+    XPathError(String),
+    JSONDecodeError(serde_json::Error),
+    CustomProcessorError(String),
+    StepMismatchError(String),
 }
 
 impl From<RegexError> for ProcessorError {
@@ -32,12 +39,37 @@ impl From<_IOError> for ProcessorError {
     }
 }
 
+impl From<serde_json::Error> for ProcessorError {
+    fn from(error: serde_json::Error) -> Self {
+        ProcessorError::JSONDecodeError(error)
+    }
+}
+
 impl From<url::ParseError> for ProcessorError {
     fn from(error: url::ParseError) -> Self {
         ProcessorError::UrlParseError(error)
     }
 }
 
+impl ProcessorError {
+    // Variant name, used to count errors by kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProcessorError::HtmlParserBuildError(_) => "HtmlParserBuildError",
+            ProcessorError::NothingToCaptureError => "NothingToCaptureError",
+            ProcessorError::RegexBuildError(_) => "RegexBuildError",
+            ProcessorError::HTTPRequestError(_) => "HTTPRequestError",
+            ProcessorError::ResponseAdoptionError(_) => "ResponseAdoptionError",
+            ProcessorError::IOError(_) => "IOError",
+            ProcessorError::UrlParseError(_) => "UrlParseError",
+            ProcessorError::AuthenticationError(_) => "AuthenticationError",
+            ProcessorError::XPathError(_) => "XPathError",
+            ProcessorError::JSONDecodeError(_) => "JSONDecodeError",
+            ProcessorError::CustomProcessorError(_) => "CustomProcessorError",
+            ProcessorError::StepMismatchError(_) => "StepMismatchError",
+        }
+    }
+}
 
 impl std::fmt::Display for ProcessorError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -50,6 +82,10 @@ impl std::fmt::Display for ProcessorError {
             ProcessorError::IOError(e) => write!(f, "{}", e),
             ProcessorError::UrlParseError(e) => write!(f, "{}", e),
             ProcessorError::AuthenticationError(mess) => write!(f, "Failed to authenticate: {}", mess),
+            ProcessorError::XPathError(mess) => write!(f, "Failed to evaluate XPath: {}", mess),
+            ProcessorError::JSONDecodeError(e) => write!(f, "Failed to decode JSON: {}", e),
+            ProcessorError::CustomProcessorError(mess) => write!(f, "Custom processor failed: {}", mess),
+            ProcessorError::StepMismatchError(mess) => write!(f, "{}", mess),
         }
     }
 }
@@ -60,4 +96,83 @@ impl std::fmt::Debug for ProcessorError {
     }
 }
 
-impl Error for ProcessorError {}
\ No newline at end of file
+impl Error for ProcessorError {}
+
+// Failure to load a crawl configuration
+pub enum ConfigError {
+    IOError(_IOError),
+    JSONDecodeError(serde_json::Error),
+}
+
+impl From<_IOError> for ConfigError {
+    fn from(error: _IOError) -> Self {
+        ConfigError::IOError(error)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(error: serde_json::Error) -> Self {
+        ConfigError::JSONDecodeError(error)
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::IOError(e) => write!(f, "Failed to read config: {}", e),
+            ConfigError::JSONDecodeError(e) => write!(f, "Invalid config: {}", e),
+        }
+    }
+}
+
+impl std::fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <ConfigError as std::fmt::Display>::fmt(self, f)
+    }
+}
+
+impl Error for ConfigError {}
+
+// Failure to open what a crawl reads before it starts
+pub enum CrawlError {
+    ReplayArchive(ReplaySource, _IOError),
+    StateStore(PathBuf, _IOError),
+}
+
+impl std::fmt::Display for CrawlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrawlError::ReplayArchive(source, e) => write!(f, "Failed to open replay archive {:?}: {}", source, e),
+            CrawlError::StateStore(path, e) => write!(f, "Failed to open state store {:?}: {}", path, e),
+        }
+    }
+}
+
+impl std::fmt::Debug for CrawlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <CrawlError as std::fmt::Display>::fmt(self, f)
+    }
+}
+
+impl Error for CrawlError {}
+
+// Failure to add a custom processor to the registry
+pub enum RegisterError {
+    DuplicateName(String),
+}
+
+impl std::fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegisterError::DuplicateName(name) => write!(f, "A processor named {:?} is already registered", name),
+        }
+    }
+}
+
+impl std::fmt::Debug for RegisterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        <RegisterError as std::fmt::Display>::fmt(self, f)
+    }
+}
+
+impl Error for RegisterError {}
@@ -0,0 +1,150 @@
+use std::fmt::Display;
+
+use log::info;
+use mime::Mime;
+use reqwest::header::{self, HeaderMap};
+use serde::Deserialize;
+use url::Url;
+
+pub enum SkipReason {
+    MissingContentType,
+    DisallowedMimeType(String),
+    TooSmall(u64),
+    TooLarge(u64),
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::MissingContentType => write!(f, "response has no Content-Type"),
+            SkipReason::DisallowedMimeType(mime) => write!(f, "MIME type {} is not allowed", mime),
+            SkipReason::TooSmall(len) => write!(f, "Content-Length {} is below the minimum", len),
+            SkipReason::TooLarge(len) => write!(f, "Content-Length {} is above the maximum", len),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct DownloadFilter {
+    #[serde(default)]
+    allowed_mime_types: Vec<String>,
+    #[serde(default)]
+    min_content_length: Option<u64>,
+    #[serde(default)]
+    max_content_length: Option<u64>,
+    #[serde(default)]
+    head_preflight: bool,
+}
+
+fn mime_matches(pattern: &str, mime_type: &Mime) -> bool {
+    match pattern.split_once('/') {
+        Some(("*", "*")) => true,
+        Some((main_type, "*")) => main_type.eq_ignore_ascii_case(mime_type.type_().as_str()),
+        _ => pattern.eq_ignore_ascii_case(mime_type.essence_str()),
+    }
+}
+
+impl DownloadFilter {
+    pub fn head_preflight(&self) -> bool {
+        self.head_preflight
+    }
+
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), SkipReason> {
+        if !self.allowed_mime_types.is_empty() {
+            let mime_type = headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<Mime>().ok())
+                .ok_or(SkipReason::MissingContentType)?;
+
+            if !self
+                .allowed_mime_types
+                .iter()
+                .any(|pattern| mime_matches(pattern, &mime_type))
+            {
+                return Err(SkipReason::DisallowedMimeType(mime_type.essence_str().to_string()));
+            }
+        }
+
+        // Responses without Content-Length can't be judged by size before download
+        let content_length = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+
+        if let Some(len) = content_length {
+            if self.min_content_length.is_some_and(|min| len < min) {
+                return Err(SkipReason::TooSmall(len));
+            }
+            if self.max_content_length.is_some_and(|max| len > max) {
+                return Err(SkipReason::TooLarge(len));
+            }
+        }
+        Ok(())
+    }
+
+    // Counted by the caller in the crawl stats
+    pub fn skip(&self, url: &Url, reason: &SkipReason) {
+        info!(url = url.as_str(); "Skip download {} ({})", url, reason);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+    use serde_json::json;
+
+    use super::*;
+
+    fn headers(content_type: Option<&'static str>, content_length: Option<&'static str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+        if let Some(content_length) = content_length {
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from_static(content_length));
+        }
+        headers
+    }
+
+    fn filter(config: serde_json::Value) -> DownloadFilter {
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn mime_patterns_match_wildcards_and_ignore_case_and_parameters() {
+        let png: Mime = "image/png".parse().unwrap();
+        let html: Mime = "text/html; charset=utf-8".parse().unwrap();
+        assert!(mime_matches("*/*", &png));
+        assert!(mime_matches("image/*", &png));
+        assert!(mime_matches("IMAGE/PNG", &png));
+        assert!(mime_matches("text/html", &html));
+        assert!(!mime_matches("image/*", &html));
+        assert!(!mime_matches("image/jpeg", &png));
+        assert!(!mime_matches("text/*html", &html));
+    }
+
+    #[test]
+    fn mime_types_are_checked_only_when_allowed_types_are_set() {
+        let images = filter(json!({"allowed_mime_types": ["image/*"]}));
+        assert!(images.check(&headers(Some("image/webp"), None)).is_ok());
+        assert!(matches!(
+            images.check(&headers(Some("text/html"), None)),
+            Err(SkipReason::DisallowedMimeType(mime)) if mime == "text/html"
+        ));
+        assert!(matches!(images.check(&headers(None, None)), Err(SkipReason::MissingContentType)));
+        assert!(matches!(images.check(&headers(Some("not a mime"), None)), Err(SkipReason::MissingContentType)));
+        assert!(filter(json!({})).check(&headers(None, None)).is_ok());
+    }
+
+    #[test]
+    fn size_limits_are_inclusive_and_need_a_content_length() {
+        let sized = filter(json!({"min_content_length": 10, "max_content_length": 100}));
+        assert!(sized.check(&headers(None, Some("10"))).is_ok());
+        assert!(sized.check(&headers(None, Some("100"))).is_ok());
+        assert!(matches!(sized.check(&headers(None, Some("9"))), Err(SkipReason::TooSmall(9))));
+        assert!(matches!(sized.check(&headers(None, Some("101"))), Err(SkipReason::TooLarge(101))));
+        assert!(sized.check(&headers(None, None)).is_ok());
+        assert!(sized.check(&headers(None, Some("unknown"))).is_ok());
+    }
+}
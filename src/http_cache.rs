@@ -0,0 +1,319 @@
+use std::fs::{create_dir_all, write};
+use std::io::Result as IOResult;
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, CACHE_CONTROL, CONTENT_LENGTH, DATE, ETAG, EXPIRES,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, TRANSFER_ENCODING,
+};
+use log::error;
+use reqwest::Request;
+use serde::Deserialize;
+use url::Url;
+
+use crate::replay::{directory_key, read_directory_entry, to_header_map, DirectoryEntry};
+use crate::response_adaptor::FetchedResponse;
+
+// When a cached response is served without asking the server at all
+#[derive(Debug, Deserialize, Clone, Default)]
+pub enum Freshness {
+    // Always send a conditional request
+    #[default]
+    Revalidate,
+    // Seconds since the last fetch or revalidation
+    MaxAge(i64),
+    // Cache-Control max-age, or Expires, of the cached response
+    Server,
+    // A cached URL is never requested again
+    Forever,
+}
+
+// Responses kept on disk as <sha256 of the URL>.json and .body, the layout replay mode reads
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpCache {
+    dir: PathBuf,
+    #[serde(default)]
+    freshness: Freshness,
+}
+
+pub struct CachedResponse {
+    entry: DirectoryEntry,
+    body: Bytes,
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &HeaderName) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+// Seconds the server allows the response to be served without revalidation
+fn server_max_age(headers: &HeaderMap) -> Option<i64> {
+    if let Some(cache_control) = header_str(headers, &CACHE_CONTROL) {
+        for directive in cache_control.split(',').map(str::trim) {
+            match directive.split_once('=') {
+                Some((name, seconds)) if name.eq_ignore_ascii_case("max-age") => {
+                    return seconds.trim_matches('"').parse().ok();
+                }
+                _ if directive.eq_ignore_ascii_case("no-cache")
+                    || directive.eq_ignore_ascii_case("no-store") =>
+                {
+                    return Some(0);
+                }
+                _ => {}
+            }
+        }
+    }
+    let expires = http_date(header_str(headers, &EXPIRES)?)?;
+    let date = header_str(headers, &DATE).and_then(http_date)?;
+    Some((expires - date).num_seconds())
+}
+
+fn is_no_store(headers: &HeaderMap) -> bool {
+    header_str(headers, &CACHE_CONTROL).is_some_and(|cache_control| {
+        cache_control
+            .split(',')
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-store"))
+    })
+}
+
+fn to_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        // The stored body is already de-chunked
+        .filter(|(name, _)| *name != TRANSFER_ENCODING)
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+impl CachedResponse {
+    pub fn is_fresh(&self, freshness: &Freshness) -> bool {
+        let age = Utc::now().timestamp() - self.entry.stored_at.unwrap_or(0);
+        match freshness {
+            Freshness::Revalidate => false,
+            Freshness::MaxAge(max_age) => age < *max_age,
+            Freshness::Server => server_max_age(&to_header_map(&self.entry.headers))
+                .is_some_and(|max_age| age < max_age),
+            Freshness::Forever => true,
+        }
+    }
+
+    // If-None-Match and If-Modified-Since from the validators the server sent last time
+    pub fn add_validators(&self, request: &mut Request) {
+        let headers = to_header_map(&self.entry.headers);
+        if let Some(etag) = headers.get(ETAG) {
+            request.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = headers.get(LAST_MODIFIED) {
+            request
+                .headers_mut()
+                .insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+
+    pub fn to_response(&self) -> FetchedResponse {
+        self.entry.to_response(self.body.clone())
+    }
+}
+
+impl HttpCache {
+    pub fn freshness(&self) -> &Freshness {
+        &self.freshness
+    }
+
+    // The cache is read and written on the blocking pool, like the replay archive
+    pub async fn lookup(&self, url: &Url) -> Option<CachedResponse> {
+        let (dir, lookup_url) = (self.dir.clone(), url.clone());
+        let read = tokio::task::spawn_blocking(move || read_directory_entry(&dir, &lookup_url))
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+        match read {
+            Ok(entry) => entry.map(|(entry, body)| CachedResponse { entry, body }),
+            Err(e) => {
+                error!(url = url.as_str(); "Failed to read {} from HTTP cache: {}", url, e);
+                None
+            }
+        }
+    }
+
+    async fn write_entry(&self, request_url: &Url, entry: DirectoryEntry, body: Option<Bytes>) -> IOResult<()> {
+        let (dir, key) = (self.dir.clone(), directory_key(request_url));
+        tokio::task::spawn_blocking(move || {
+            create_dir_all(&dir)?;
+            // The body goes first, so an entry is never found without one
+            if let Some(body) = body {
+                write(dir.join(format!("{}.body", key)), body)?;
+            }
+            write(dir.join(format!("{}.json", key)), serde_json::to_vec(&entry)?)
+        })
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+    }
+
+    // Keyed by the requested URL, so a redirected request finds its final response
+    pub async fn store(&self, request_url: &Url, response: &FetchedResponse) {
+        if !response.status.is_success() || is_no_store(&response.headers) {
+            return;
+        }
+        let entry = DirectoryEntry {
+            url: response.url.clone(),
+            status: response.status.as_u16(),
+            headers: to_pairs(&response.headers),
+            stored_at: Some(Utc::now().timestamp()),
+        };
+        if let Err(e) = self.write_entry(request_url, entry, Some(response.body.clone())).await {
+            error!(url = request_url.as_str(); "Failed to write {} to HTTP cache: {}", request_url, e);
+        }
+    }
+
+    // A 304 refreshes the stored headers and restarts the freshness clock
+    pub async fn revalidated(
+        &self,
+        request_url: &Url,
+        cached: CachedResponse,
+        not_modified: &HeaderMap,
+    ) -> FetchedResponse {
+        let mut headers = to_header_map(&cached.entry.headers);
+        for name in not_modified.keys() {
+            if name == CONTENT_LENGTH || name == TRANSFER_ENCODING {
+                continue;
+            }
+            headers.remove(name);
+            for value in not_modified.get_all(name) {
+                headers.append(name.clone(), HeaderValue::clone(value));
+            }
+        }
+        let entry = DirectoryEntry {
+            headers: to_pairs(&headers),
+            stored_at: Some(Utc::now().timestamp()),
+            ..cached.entry
+        };
+        if let Err(e) = self.write_entry(request_url, entry.clone(), None).await {
+            error!(url = request_url.as_str(); "Failed to update {} in HTTP cache: {}", request_url, e);
+        }
+        entry.to_response(cached.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+
+    use reqwest::{Method, StatusCode, Version};
+    use serde_json::json;
+
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        to_header_map(
+            &pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn cached(pairs: &[(&str, &str)], age: i64) -> CachedResponse {
+        CachedResponse {
+            entry: DirectoryEntry {
+                url: Url::parse("https://example.com/").unwrap(),
+                status: 200,
+                headers: to_pairs(&headers(pairs)),
+                stored_at: Some(Utc::now().timestamp() - age),
+            },
+            body: Bytes::from_static(b"cached"),
+        }
+    }
+
+    fn response(pairs: &[(&str, &str)], status: StatusCode) -> FetchedResponse {
+        FetchedResponse {
+            url: Url::parse("https://example.com/final").unwrap(),
+            status,
+            version: Version::HTTP_11,
+            headers: headers(pairs),
+            body: Bytes::from_static(b"fresh body"),
+        }
+    }
+
+    fn cache() -> HttpCache {
+        let dir = std::env::temp_dir().join(format!("crawler-cache-{}", nanoid::nanoid!(8)));
+        serde_json::from_value(json!({"dir": dir, "freshness": "Server"})).unwrap()
+    }
+
+    #[test]
+    fn server_max_age_reads_cache_control_then_expires() {
+        assert_eq!(server_max_age(&headers(&[("cache-control", "public, max-age=\"60\"")])), Some(60));
+        assert_eq!(server_max_age(&headers(&[("cache-control", "no-cache")])), Some(0));
+        let expires = [
+            ("date", "Mon, 05 Jan 2026 10:00:00 GMT"),
+            ("expires", "Mon, 05 Jan 2026 10:02:00 GMT"),
+        ];
+        assert_eq!(server_max_age(&headers(&expires)), Some(120));
+        assert_eq!(server_max_age(&headers(&[("expires", "Mon, 05 Jan 2026 10:02:00 GMT")])), None);
+    }
+
+    #[test]
+    fn freshness_depends_on_the_setting_and_the_age() {
+        let response = cached(&[("cache-control", "max-age=300")], 100);
+        assert!(!response.is_fresh(&Freshness::Revalidate));
+        assert!(response.is_fresh(&Freshness::MaxAge(200)));
+        assert!(!response.is_fresh(&Freshness::MaxAge(50)));
+        assert!(response.is_fresh(&Freshness::Server));
+        assert!(!cached(&[("cache-control", "max-age=30")], 100).is_fresh(&Freshness::Server));
+        assert!(!cached(&[], 0).is_fresh(&Freshness::Server));
+        assert!(cached(&[], 1_000_000).is_fresh(&Freshness::Forever));
+    }
+
+    #[test]
+    fn validators_become_conditional_headers() {
+        let response = cached(&[("etag", "\"v1\""), ("last-modified", "Mon, 05 Jan 2026 10:00:00 GMT")], 0);
+        let mut request = Request::new(Method::GET, Url::parse("https://example.com/").unwrap());
+        response.add_validators(&mut request);
+        assert_eq!(request.headers()[IF_NONE_MATCH], "\"v1\"");
+        assert_eq!(request.headers()[IF_MODIFIED_SINCE], "Mon, 05 Jan 2026 10:00:00 GMT");
+    }
+
+    #[tokio::test]
+    async fn only_storable_responses_are_cached_under_the_requested_url() {
+        let cache = cache();
+        let requested = Url::parse("https://example.com/redirected").unwrap();
+        cache.store(&requested, &response(&[("cache-control", "no-store")], StatusCode::OK)).await;
+        cache.store(&requested, &response(&[], StatusCode::NOT_FOUND)).await;
+        assert!(cache.lookup(&requested).await.is_none());
+
+        cache.store(&requested, &response(&[("etag", "\"v1\"")], StatusCode::OK)).await;
+        let found = cache.lookup(&requested).await.unwrap().to_response();
+        assert_eq!(found.url.as_str(), "https://example.com/final");
+        assert_eq!(found.body, Bytes::from_static(b"fresh body"));
+        remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_304_refreshes_headers_and_keeps_the_body() {
+        let cache = cache();
+        let requested = Url::parse("https://example.com/").unwrap();
+        let first = [("etag", "\"v1\""), ("content-length", "10"), ("cache-control", "max-age=0")];
+        cache.store(&requested, &response(&first, StatusCode::OK)).await;
+        let stale = cache.lookup(&requested).await.unwrap();
+        assert!(!stale.is_fresh(cache.freshness()));
+
+        let not_modified = headers(&[("etag", "\"v2\""), ("content-length", "0"), ("cache-control", "max-age=60")]);
+        let revalidated = cache.revalidated(&requested, stale, &not_modified).await;
+        assert_eq!(revalidated.body, Bytes::from_static(b"fresh body"));
+        assert_eq!(revalidated.headers[ETAG], "\"v2\"");
+        assert_eq!(revalidated.headers[CONTENT_LENGTH], "10");
+
+        // The next run finds the refreshed headers next to the old body
+        let stored = cache.lookup(&requested).await.unwrap();
+        assert!(stored.is_fresh(cache.freshness()));
+        assert_eq!(stored.to_response().headers[ETAG], "\"v2\"");
+        assert_eq!(stored.to_response().body, Bytes::from_static(b"fresh body"));
+        remove_dir_all(&cache.dir).unwrap();
+    }
+}
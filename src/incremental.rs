@@ -0,0 +1,405 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Result as IOResult};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::Utc;
+use log::{warn, Level};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::logging::log_ctx;
+use crate::parser::{NextProcessingStep, ProcessingResultUnit, ResultContext, StepInput};
+use crate::response_adaptor::Resp;
+use crate::storage::{sha256_hex, JsonLinesWriter};
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub enum Change {
+    #[default]
+    NewOrChanged,
+    New,
+    Changed,
+}
+
+// Runs `next_steps` only for items that are new or changed since the last run
+#[derive(Debug, Deserialize, Clone)]
+pub struct OnlyWhen {
+    #[serde(default)]
+    change: Change,
+    // Record field, captured field or URL parameter identifying an item
+    #[serde(default)]
+    key: Option<String>,
+    // Keeps the state of this step when the pipeline around it is edited
+    #[serde(default)]
+    name: Option<String>,
+    next_steps: Vec<NextProcessingStep>,
+}
+
+pub enum ItemState {
+    New,
+    Changed,
+    Unchanged,
+    // Passed for another task whose steps have not finished yet
+    InProgress,
+}
+
+// (scope, item key) -> content hash, loaded from the state file and kept in memory for the run
+#[derive(Debug)]
+struct ItemHashes {
+    hashes: HashMap<(String, String), String>,
+    in_progress: HashSet<(String, String)>,
+    writer: JsonLinesWriter,
+}
+
+impl ItemHashes {
+    // Only new and changed items are appended, with the time they were seen
+    fn record(&mut self, item: (String, String), sha256: &str) -> IOResult<()> {
+        self.writer.write_value(&json!({
+            "scope": item.0,
+            "key": item.1,
+            "sha256": sha256,
+            "seen_at": Utc::now().to_rfc3339(),
+        }))?;
+        // A killed crawl must not forget items it already recorded as seen
+        self.writer.flush()?;
+        self.hashes.insert(item, sha256.to_string());
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct StateStore {
+    items: Mutex<ItemHashes>,
+}
+
+// An item that passed its OnlyWhen, recorded as seen only once the steps below it succeeded.
+// Without a state store there is nothing to record.
+pub struct PendingItem<'a> {
+    state: Option<&'a StateStore>,
+    item: (String, String),
+    sha256: String,
+}
+
+impl PendingItem<'_> {
+    fn untracked(key: &str) -> Self {
+        PendingItem {
+            state: None,
+            item: (String::new(), key.to_string()),
+            sha256: String::new(),
+        }
+    }
+
+    // Run `next_steps` with a fresh failure flag in `steps_ctx` before finishing the item
+    pub fn finish(self, ctx: &ResultContext, steps_ctx: &ResultContext) {
+        // An OnlyWhen further up must not record its item either
+        if steps_ctx.has_failed() {
+            ctx.mark_failed();
+        }
+        let state = match self.state {
+            Some(state) => state,
+            None => return,
+        };
+        if steps_ctx.has_failed() {
+            log_ctx!(Level::Warn, ctx, "Not recording {} as seen, a step below {} failed", self.item.1, ctx.step_path);
+            return;
+        }
+        if let Err(e) = state.lock().record(self.item.clone(), &self.sha256) {
+            log_ctx!(Level::Error, ctx, "Failed to update state of {}: {}", self.item.1, e);
+        }
+    }
+}
+
+// Recorded or not, the item is free to pass again for the next task that sees it
+impl Drop for PendingItem<'_> {
+    fn drop(&mut self) {
+        if let Some(state) = self.state {
+            state.lock().in_progress.remove(&self.item);
+        }
+    }
+}
+
+fn load_hashes(path: &Path) -> IOResult<HashMap<(String, String), String>> {
+    let mut hashes = HashMap::new();
+    if !path.exists() {
+        return Ok(hashes);
+    }
+    for line in BufReader::new(File::open(path)?).lines() {
+        let entry: Value = match serde_json::from_str(&line?) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Skip broken line in {:?}: {}", path, e);
+                continue;
+            }
+        };
+        // Later lines win, so every item maps to the hash it had last time
+        if let (Some(scope), Some(key), Some(sha256)) = (
+            entry["scope"].as_str(),
+            entry["key"].as_str(),
+            entry["sha256"].as_str(),
+        ) {
+            hashes.insert((scope.to_string(), key.to_string()), sha256.to_string());
+        }
+    }
+    Ok(hashes)
+}
+
+impl StateStore {
+    pub fn open(path: &Path) -> IOResult<Self> {
+        Ok(StateStore {
+            items: Mutex::new(ItemHashes {
+                hashes: load_hashes(path)?,
+                in_progress: HashSet::new(),
+                writer: JsonLinesWriter::open(path, None)?,
+            }),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ItemHashes> {
+        self.items.lock().expect("State store lock is poisoned")
+    }
+
+    pub fn tracked_items(&self) -> usize {
+        self.lock().hashes.len()
+    }
+
+    // Passing items are handed out as pending and written by `PendingItem::commit`,
+    // items that don't pass are written right away since no steps run for them
+    pub fn check(
+        &self,
+        scope: &str,
+        key: &str,
+        sha256: &str,
+        passes: impl FnOnce(&ItemState) -> bool,
+    ) -> IOResult<(ItemState, Option<PendingItem<'_>>)> {
+        let mut items = self.lock();
+        let item = (scope.to_string(), key.to_string());
+        if items.in_progress.contains(&item) {
+            return Ok((ItemState::InProgress, None));
+        }
+        let state = match items.hashes.get(&item) {
+            Some(previous) if previous == sha256 => return Ok((ItemState::Unchanged, None)),
+            Some(_) => ItemState::Changed,
+            None => ItemState::New,
+        };
+        if !passes(&state) {
+            items.record(item, sha256)?;
+            return Ok((state, None));
+        }
+        items.in_progress.insert(item.clone());
+        let pending = PendingItem {
+            state: Some(self),
+            item,
+            sha256: sha256.to_string(),
+        };
+        Ok((state, Some(pending)))
+    }
+}
+
+impl OnlyWhen {
+    pub fn next_steps(&self) -> &[NextProcessingStep] {
+        &self.next_steps
+    }
+
+    // A URL is its own key and its own content, so on its own it can only be new or unchanged
+    pub fn check(&self, input: StepInput, step_path: &str) -> Result<(), String> {
+        match (self.change, input, &self.key) {
+            (Change::Changed, StepInput::URL, None) => Err(format!(
+                "OnlyWhen step at {} can never see a URL change, use a key or change \"New\"",
+                step_path
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn configured_key(&self, ctx: &ResultContext) -> Option<String> {
+        let key = self.key.as_ref()?;
+        let value = ctx
+            .vars
+            .get(&format!("capture.{}", key))
+            .or_else(|| ctx.vars.get(&format!("param.{}", key)));
+        if value.is_none() {
+            log_ctx!(Level::Warn, ctx, "No {:?} to identify items at {}", key, ctx.step_path);
+        }
+        value.cloned()
+    }
+
+    // `None` skips the item
+    fn passes<'a>(
+        &self,
+        state: Option<&'a StateStore>,
+        ctx: &ResultContext,
+        key: &str,
+        content: &[u8],
+    ) -> Option<PendingItem<'a>> {
+        // Warned about once, when the state store would have been opened
+        let state = match state {
+            Some(state) => state,
+            None => return Some(PendingItem::untracked(key)),
+        };
+        let scope = self.name.as_ref().unwrap_or(&ctx.step_path);
+        let change = self.change;
+        let checked = state.check(scope, key, &sha256_hex(content), |item_state| {
+            matches!(
+                (change, item_state),
+                (Change::New | Change::NewOrChanged, ItemState::New)
+                    | (Change::Changed | Change::NewOrChanged, ItemState::Changed)
+            )
+        });
+        match checked {
+            Ok((_, Some(pending))) => Some(pending),
+            Ok((item_state, None)) => {
+                let reason = match item_state {
+                    ItemState::Unchanged => "unchanged since last run",
+                    ItemState::InProgress => "already being processed",
+                    _ => "seen before",
+                };
+                log_ctx!(Level::Info, ctx, "Skip {} ({})", key, reason);
+                None
+            }
+            Err(e) => {
+                log_ctx!(Level::Error, ctx, "Failed to update state of {}: {}", key, e);
+                Some(PendingItem::untracked(key))
+            }
+        }
+    }
+
+    // Responses are identified by their URL unless a key is configured
+    pub fn passes_response<'a>(
+        &self,
+        state: Option<&'a StateStore>,
+        ctx: &ResultContext,
+        resp: &Resp,
+    ) -> Option<PendingItem<'a>> {
+        let content = match resp {
+            Resp::RespText(text) => text.as_bytes(),
+            Resp::RespBytes { bts, .. } => bts,
+        };
+        let key = self
+            .configured_key(ctx)
+            .unwrap_or_else(|| ctx.source_url.to_string());
+        self.passes(state, ctx, &key, content)
+    }
+
+    // URLs are their own key; other results without a key are identified by their content
+    pub fn passes_result<'a>(
+        &self,
+        state: Option<&'a StateStore>,
+        ctx: &ResultContext,
+        result: &ProcessingResultUnit,
+    ) -> Option<PendingItem<'a>> {
+        let content = result.to_json_value().to_string();
+        let key_ctx = match result {
+            ProcessingResultUnit::Record(record) => ctx.with_record(record),
+            _ => ctx.clone(),
+        };
+        let key = self.configured_key(&key_ctx).unwrap_or_else(|| match result {
+            ProcessingResultUnit::URL(url) => url.to_string(),
+            _ => sha256_hex(content.as_bytes()),
+        });
+        self.passes(state, ctx, &key, content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use url::Url;
+
+    use super::*;
+    use crate::parser::{check_steps, has_only_when};
+
+    fn state_path() -> PathBuf {
+        std::env::temp_dir().join(format!("crawler-state-{}.jsonl", nanoid::nanoid!(8)))
+    }
+
+    fn new_or_changed(state: &ItemState) -> bool {
+        matches!(state, ItemState::New | ItemState::Changed)
+    }
+
+    fn ctx() -> ResultContext {
+        ResultContext::new(Url::parse("https://example.com/").unwrap(), "/scraper".to_string())
+    }
+
+    #[test]
+    fn items_are_recorded_only_when_finished_without_failures() {
+        let path = state_path();
+        let store = StateStore::open(&path).unwrap();
+
+        let (_, pending) = store.check("scope", "a", "1", new_or_changed).unwrap();
+        let steps_ctx = ctx().tracking_failures();
+        steps_ctx.mark_failed();
+        pending.unwrap().finish(&ctx(), &steps_ctx);
+        assert_eq!(store.tracked_items(), 0);
+
+        let (_, pending) = store.check("scope", "a", "1", new_or_changed).unwrap();
+        pending.unwrap().finish(&ctx(), &ctx().tracking_failures());
+        assert_eq!(store.tracked_items(), 1);
+        assert!(matches!(
+            store.check("scope", "a", "1", new_or_changed).unwrap(),
+            (ItemState::Unchanged, None)
+        ));
+
+        // Written through to the file without waiting for the store to drop
+        let reloaded = StateStore::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.tracked_items(), 1);
+    }
+
+    #[test]
+    fn a_pending_item_does_not_pass_twice() {
+        let path = state_path();
+        let store = StateStore::open(&path).unwrap();
+
+        let (_, pending) = store.check("scope", "a", "1", new_or_changed).unwrap();
+        assert!(matches!(
+            store.check("scope", "a", "1", new_or_changed).unwrap(),
+            (ItemState::InProgress, None)
+        ));
+        drop(pending);
+        assert!(matches!(
+            store.check("scope", "a", "1", new_or_changed).unwrap(),
+            (ItemState::New, Some(_))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn items_that_do_not_pass_are_recorded_right_away() {
+        let path = state_path();
+        let store = StateStore::open(&path).unwrap();
+
+        let only_changed = |state: &ItemState| matches!(state, ItemState::Changed);
+        assert!(matches!(store.check("scope", "a", "1", only_changed).unwrap(), (ItemState::New, None)));
+        assert!(matches!(store.check("scope", "a", "2", only_changed).unwrap(), (ItemState::Changed, Some(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failures_reach_the_only_when_further_up() {
+        let outer = ctx().tracking_failures();
+        let inner = outer.child("/OnlyWhen/next_steps").tracking_failures();
+        inner.child("/0").mark_failed();
+        PendingItem::untracked("a").finish(&outer, &inner);
+        assert!(outer.has_failed());
+    }
+
+    #[test]
+    fn changed_urls_need_a_key() {
+        let check = |only_when: Value, input: StepInput| {
+            let steps: Vec<NextProcessingStep> = serde_json::from_value(json!([{ "OnlyWhen": only_when }])).unwrap();
+            assert!(has_only_when(&steps));
+            check_steps(&steps, input, "/scraper/targets/Text/0/Process/next_steps")
+        };
+        let changed = json!({"change": "Changed", "next_steps": []});
+        assert_eq!(
+            check(changed.clone(), StepInput::URL).unwrap_err(),
+            "OnlyWhen step at /scraper/targets/Text/0/Process/next_steps/0 can never see a URL change, \
+             use a key or change \"New\""
+        );
+        assert!(check(changed, StepInput::Str).is_ok());
+        assert!(check(json!({"change": "Changed", "key": "id", "next_steps": []}), StepInput::URL).is_ok());
+        assert!(check(json!({"change": "New", "next_steps": []}), StepInput::URL).is_ok());
+    }
+}
@@ -0,0 +1,34 @@
+pub mod auth;
+pub mod client_config;
+pub mod crawler;
+pub mod custom_types;
+pub mod errors;
+pub mod filters;
+pub mod scraper_unit;
+pub mod headers;
+pub mod http_cache;
+pub mod incremental;
+pub mod limits;
+pub mod logging;
+pub mod metrics;
+pub mod parser;
+pub mod processor;
+pub mod progress;
+pub mod record;
+pub mod replay;
+pub mod response_adaptor;
+pub mod sink;
+pub mod storage;
+pub mod scraper_job;
+pub mod stats;
+pub mod warc;
+pub mod script_json;
+pub mod xpath;
+
+#[cfg(test)]
+mod test_server;
+
+pub use crawler::{CrawlSummary, Crawler};
+pub use errors::{ConfigError, CrawlError, RegisterError};
+pub use processor::{register_processor, Processor};
+pub use sink::{CrawlResult, ResultSink};
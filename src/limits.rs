@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Deserialize;
+
+const DEFAULT_CONCURRENCY: usize = 15;
+
+#[derive(Debug, Deserialize, Default)]
+pub struct CrawlLimits {
+    // ScraperJobs run at once
+    #[serde(default)]
+    concurrency: Option<usize>,
+    // Nested Scrape steps below the start URLs
+    #[serde(default)]
+    max_depth: Option<usize>,
+    // Requests over the whole run, including the ones served from a cache or archive
+    #[serde(default)]
+    max_pages: Option<u64>,
+    #[serde(skip)]
+    pages_started: AtomicU64,
+}
+
+impl CrawlLimits {
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1)
+    }
+
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = Some(concurrency);
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = Some(max_depth);
+    }
+
+    pub fn set_max_pages(&mut self, max_pages: u64) {
+        self.max_pages = Some(max_pages);
+    }
+
+    pub fn allows_depth(&self, depth: usize) -> bool {
+        self.max_depth.is_none_or(|max_depth| depth <= max_depth)
+    }
+
+    // Takes one page from the budget, false once it is used up
+    pub fn start_page(&self) -> bool {
+        match self.max_pages {
+            Some(max_pages) => self.pages_started.fetch_add(1, Ordering::Relaxed) < max_pages,
+            None => true,
+        }
+    }
+}
@@ -1,20 +1,216 @@
-use log::LevelFilter;
+use std::path::PathBuf;
+
+use log::kv::Key;
+use log::{LevelFilter, Record};
+use log4rs::append::console::{ConsoleAppender, Target};
 use log4rs::append::file::FileAppender;
+use log4rs::encode::json::JsonEncoder;
 use log4rs::encode::pattern::PatternEncoder;
-use log4rs::config::{Appender, Config, Root};
+use log4rs::encode::{Encode, Write};
+use log4rs::config::{Appender, Config, Logger, Root};
+use serde::{de, Deserialize, Deserializer};
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub enum LogFormat {
+    #[default]
+    Pattern,
+    // One JSON object per event, crawl context under `attributes`
+    Json,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct LogSettings {
+    #[serde(default)]
+    file: Option<PathBuf>,
+    #[serde(default, deserialize_with = "de_level")]
+    level: Option<LevelFilter>,
+    #[serde(default)]
+    format: Option<LogFormat>,
+    #[serde(skip)]
+    without_console: bool,
+}
+
+fn de_level<'de, D>(deserializer: D) -> Result<Option<LevelFilter>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw_level: String = Deserialize::deserialize(deserializer)?;
+    raw_level.parse().map(Some).map_err(de::Error::custom)
+}
+
+fn parse_format(raw_format: &str) -> Result<LogFormat, String> {
+    match raw_format.to_lowercase().as_str() {
+        "pattern" => Ok(LogFormat::Pattern),
+        "json" => Ok(LogFormat::Json),
+        _ => Err(format!("Unknown log format {:?}, expected json or pattern", raw_format)),
+    }
+}
+
+impl LogSettings {
+    // --log-file, --log-level and --log-format win over the config
+    pub fn with_args(mut self, args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = args;
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", flag));
+            match flag.as_str() {
+                "--log-file" => self.file = Some(PathBuf::from(value()?)),
+                "--log-level" => {
+                    self.level = Some(value()?.parse().map_err(|e| format!("{}: {}", flag, e))?)
+                }
+                "--log-format" => self.format = Some(parse_format(&value()?)?),
+                _ => return Err(format!("Unknown argument {:?}", flag)),
+            }
+        }
+        Ok(self)
+    }
 
-pub fn configure_log() -> Result<(), Box<dyn std::error::Error>> {
+    // The live progress view owns the terminal, so events only go to the file
+    pub fn without_console(mut self) -> Self {
+        self.without_console = true;
+        self
+    }
+}
+
+// Crawl context set by `log_ctx!`, `log_job!` and fetch events
+const CONTEXT_KEYS: [&str; 6] = ["job_id", "depth", "url", "step_path", "status", "duration_ms"];
+
+// The pattern line followed by the context fields the event has, e.g. `... - Fetched ... job_id=abc depth=1`
+#[derive(Debug)]
+struct PatternWithContext(PatternEncoder);
+
+impl Encode for PatternWithContext {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        self.0.encode(w, record)?;
+        let key_values = record.key_values();
+        for key in CONTEXT_KEYS {
+            if let Some(value) = key_values.get(Key::from_str(key)) {
+                write!(w, " {}={}", key, value)?;
+            }
+        }
+        writeln!(w)?;
+        Ok(())
+    }
+}
+
+fn encoder(format: LogFormat) -> Box<dyn Encode> {
+    match format {
+        LogFormat::Pattern => Box::new(PatternWithContext(PatternEncoder::new(
+            "{d(%Y-%m-%d %H:%M:%S)} {l} {t} - {m}",
+        ))),
+        LogFormat::Json => Box::new(JsonEncoder::new()),
+    }
+}
+
+pub fn configure_log(settings: &LogSettings) -> Result<(), Box<dyn std::error::Error>> {
+    let format = settings.format.unwrap_or_default();
+    let file = settings.file.clone().unwrap_or_else(|| PathBuf::from("logs.log"));
     let logfile = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{l} - {m}\n")))
-        .build("logs.log")?;
+        .encoder(encoder(format))
+        .build(file)?;
+    let console = ConsoleAppender::builder()
+        .target(Target::Stderr)
+        .encoder(encoder(format))
+        .build();
 
+    let level = settings.level.unwrap_or(LevelFilter::Info);
+    let mut root = Root::builder().appender("logfile");
+    if !settings.without_console {
+        root = root.appender("console");
+    }
+    // Debug output of HTML parsing and HTTP libraries would drown the crawl events
     let config = Config::builder()
         .appender(Appender::builder().build("logfile", Box::new(logfile)))
-        .build(Root::builder()
-                   .appender("logfile")
-                   .build(LevelFilter::Info))?;
-
+        .appender(Appender::builder().build("console", Box::new(console)))
+        .logger(Logger::builder().build(env!("CARGO_CRATE_NAME"), level))
+        .build(root.build(level.min(LevelFilter::Info)))?;
 
     log4rs::init_config(config)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+// Logs an event of a result with its crawl context as structured fields
+macro_rules! log_ctx {
+    ($level:expr, $ctx:expr, $($arg:tt)+) => {
+        log::log!(
+            $level,
+            job_id = $ctx.job_id.as_str(),
+            depth = $ctx.depth,
+            url = $ctx.source_url.as_str(),
+            step_path = $ctx.step_path.as_str();
+            $($arg)+
+        )
+    };
+}
+pub(crate) use log_ctx;
+
+// Logs an event of a ScraperJob run for one URL
+macro_rules! log_job {
+    ($level:expr, $job:expr, $url:expr, $($arg:tt)+) => {
+        log::log!(
+            $level,
+            job_id = $job.job_id.as_str(),
+            depth = $job.depth,
+            url = $url.as_str(),
+            step_path = $job.step_path.as_str();
+            $($arg)+
+        )
+    };
+}
+pub(crate) use log_job;
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+
+    struct Buffer(Vec<u8>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Write for Buffer {}
+
+    fn render(key_values: &[(&str, &str)]) -> String {
+        let encoder = PatternWithContext(PatternEncoder::new("{l} {t} - {m}"));
+        let mut buffer = Buffer(vec![]);
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("generic_web_crawler::scraper_job")
+            .args(format_args!("Fetched https://example.com/"))
+            .key_values(&key_values)
+            .build();
+        encoder.encode(&mut buffer, &record).unwrap();
+        String::from_utf8(buffer.0).unwrap()
+    }
+
+    #[test]
+    fn context_fields_follow_the_message_in_a_fixed_order() {
+        let line = render(&[
+            ("duration_ms", "12"),
+            ("status", "200"),
+            ("step_path", "/scraper"),
+            ("url", "https://example.com/"),
+            ("depth", "1"),
+            ("job_id", "abc"),
+            ("other", "ignored"),
+        ]);
+        assert_eq!(
+            line,
+            "INFO generic_web_crawler::scraper_job - Fetched https://example.com/ \
+             job_id=abc depth=1 url=https://example.com/ step_path=/scraper status=200 duration_ms=12\n"
+        );
+    }
+
+    #[test]
+    fn events_without_context_are_plain_lines() {
+        assert_eq!(render(&[]), "INFO generic_web_crawler::scraper_job - Fetched https://example.com/\n");
+    }
+}
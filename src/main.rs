@@ -1,43 +1,45 @@
-pub mod auth;
-pub mod client_config;
-pub mod custom_types;
-pub mod errors;
-pub mod scraper_unit;
-pub mod headers;
-pub mod logging;
-pub mod parser;
-pub mod response_adaptor;
-pub mod storage;
-pub mod scraper_job;
-
-use std::pin::Pin;
-use std::fs;
-
-use futures::{Future, StreamExt};
-
-use scraper_unit::ScraperUnit;
-use tokio::sync::mpsc::channel;
-use tokio_stream::wrappers::ReceiverStream;
-
-pub fn new_dummy_scraper() -> ScraperUnit {
-    let raw = fs::read_to_string("./json_templates/olx.json").unwrap();
-    serde_json::from_str(&raw).unwrap()
-}
+use std::{env, process};
 
+use generic_web_crawler::logging::configure_log;
+use generic_web_crawler::Crawler;
 
 #[tokio::main]
 pub async fn main() {
-    let (tx, rx) = channel::<Pin<Box<(dyn Future<Output = ()> + Send)>>>(15);
+    dotenv::dotenv().ok();
 
-    tokio::spawn(async move {
-        if tx.send(Box::pin(new_dummy_scraper().run(tx.clone()))).await.is_err() {
-             eprintln!("Failed to push initial Scaper Unit to the channel.");
+    let mut crawler = match Crawler::from_file("./json_templates/olx.json") {
+        Ok(crawler) => crawler,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
         }
-    });
-
-    let stream: ReceiverStream<_> = rx.into();
-
-    stream.for_each_concurrent(15, |fut| async {
-        fut.await;
-    }).await;
-}
\ No newline at end of file
+    };
+    // REPLAY_FROM lets an unchanged config run offline, e.g. REPLAY_FROM=warc:archive
+    if let Ok(source) = env::var("REPLAY_FROM") {
+        match source.parse() {
+            Ok(source) => crawler = crawler.replay(source),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(2);
+            }
+        }
+    }
+    let mut log_settings = match crawler.log_settings().clone().with_args(env::args().skip(1)) {
+        Ok(log_settings) => log_settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    if crawler.shows_live_progress() {
+        log_settings = log_settings.without_console();
+    }
+    if let Err(e) = configure_log(&log_settings) {
+        eprintln!("Failed to configure logging: {}", e);
+    }
+
+    if let Err(e) = crawler.run().await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt::{Display, Write};
+use std::io::Result as IOResult;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use log::{debug, error, info};
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::stats::CrawlStats;
+
+// Request lines and headers beyond this are not read
+const MAX_REQUEST_BYTES: u64 = 16 * 1024;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MetricsSettings {
+    // e.g. "127.0.0.1:9184"
+    pub listen: SocketAddr,
+}
+
+// Prometheus text exposition format, version 0.0.4
+struct Exposition {
+    text: String,
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        let labels = labels
+            .iter()
+            .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+            .collect::<Vec<String>>();
+        if labels.is_empty() {
+            let _ = writeln!(self.text, "{} {}", name, value);
+        } else {
+            let _ = writeln!(self.text, "{}{{{}}} {}", name, labels.join(","), value);
+        }
+    }
+
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+}
+
+pub fn render(stats: &CrawlStats) -> String {
+    let data = stats.snapshot();
+    let mut out = Exposition { text: String::new() };
+
+    out.single("crawler_uptime_seconds", "gauge", "Seconds since the crawl started.", stats.elapsed().as_secs_f64());
+    out.single("crawler_in_flight_requests", "gauge", "HTTP requests waiting for a response.", data.in_flight_requests);
+    out.single("crawler_queued_jobs", "gauge", "Scrape jobs waiting for a slot in the job channel.", data.queued_jobs);
+    out.single("crawler_running_jobs", "gauge", "Scrape jobs being run.", data.running_jobs);
+
+    out.single("crawler_max_depth", "gauge", "Deepest nested Scrape job started.", data.max_depth);
+
+    out.family("crawler_pages_total", "counter", "Pages done or failed.");
+    out.sample("crawler_pages_total", &[("outcome", "done")], data.pages_done);
+    out.sample("crawler_pages_total", &[("outcome", "failed")], data.pages_failed);
+    out.family("crawler_requests_total", "counter", "HTTP responses received, by host.");
+    for (host, count) in &data.requests_per_host {
+        out.sample("crawler_requests_total", &[("host", host)], count);
+    }
+    out.family("crawler_responses_total", "counter", "HTTP responses received, by status code.");
+    for (status, count) in &data.status_codes {
+        out.sample("crawler_responses_total", &[("status", &status.to_string())], count);
+    }
+    out.single("crawler_downloaded_bytes_total", "counter", "Bytes of response bodies received.", data.bytes_downloaded);
+
+    let latency = &data.latency_ms;
+    out.family("crawler_request_duration_seconds", "histogram", "Time from sending a request to reading its body.");
+    let mut cumulative = 0;
+    for (bound_ms, count) in latency.bounds_ms.iter().zip(&latency.counts) {
+        cumulative += count;
+        let le = (*bound_ms as f64 / 1000.0).to_string();
+        out.sample("crawler_request_duration_seconds_bucket", &[("le", &le)], cumulative);
+    }
+    out.sample("crawler_request_duration_seconds_bucket", &[("le", "+Inf")], latency.count);
+    out.sample("crawler_request_duration_seconds_sum", &[], latency.sum_ms as f64 / 1000.0);
+    out.sample("crawler_request_duration_seconds_count", &[], latency.count);
+
+    out.single("crawler_from_cache_total", "counter", "Responses served from the HTTP cache.", data.from_cache);
+    out.single("crawler_replayed_total", "counter", "Responses served from a replay archive.", data.replayed);
+    out.single("crawler_filtered_total", "counter", "Downloads skipped by a filter.", data.filtered);
+
+    out.family("crawler_step_results_total", "counter", "Results produced, by step path.");
+    for (step_path, step) in &data.steps {
+        out.sample("crawler_step_results_total", &[("step", step_path)], step.results);
+    }
+    out.family("crawler_step_empty_total", "counter", "Step runs that captured nothing, by step path.");
+    for (step_path, step) in &data.steps {
+        out.sample("crawler_step_empty_total", &[("step", step_path)], step.empty);
+    }
+    out.family("crawler_step_errors_total", "counter", "Step runs that failed, by step path.");
+    for (step_path, step) in &data.steps {
+        out.sample("crawler_step_errors_total", &[("step", step_path)], step.errors);
+    }
+
+    out.family("crawler_stored_total", "counter", "Store attempts, by storage backend and outcome.");
+    for (kind, storage) in &data.storage {
+        for (outcome, count) in [
+            ("written", storage.written),
+            ("skipped", storage.skipped),
+            ("failed", storage.failed),
+        ] {
+            out.sample("crawler_stored_total", &[("backend", kind), ("outcome", outcome)], count);
+        }
+    }
+    out.family("crawler_stored_bytes_total", "counter", "Bytes written, by storage backend.");
+    for (kind, storage) in &data.storage {
+        out.sample("crawler_stored_bytes_total", &[("backend", kind)], storage.bytes);
+    }
+
+    out.family("crawler_errors_total", "counter", "Errors, by kind.");
+    for (kind, count) in &data.errors {
+        out.sample("crawler_errors_total", &[("kind", kind)], count);
+    }
+    out.text
+}
+
+async fn respond(mut socket: TcpStream, stats: &CrawlStats) -> IOResult<()> {
+    let mut request_line = String::new();
+    {
+        let mut reader = BufReader::new((&mut socket).take(MAX_REQUEST_BYTES));
+        reader.read_line(&mut request_line).await?;
+        // Headers are ignored, but read so the client is not reset before it gets the response
+        let mut header = String::new();
+        while reader.read_line(&mut header).await? > 2 {
+            header.clear();
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let (status, content_type, body) = match (method, path.split('?').next()) {
+        ("GET", Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", render(stats)),
+        ("GET", _) => ("404 Not Found", "text/plain", "Metrics are served at /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "Only GET is supported\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+// Runs until the process exits; a failed bind only disables the endpoint
+pub async fn serve(settings: MetricsSettings, stats: Arc<CrawlStats>) {
+    let listener = match TcpListener::bind(settings.listen).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Cannot serve metrics on {}: {}", settings.listen, e);
+            return;
+        }
+    };
+    info!("Serving metrics on http://{}/metrics", settings.listen);
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                error!("Failed to accept metrics connection: {}", e);
+                continue;
+            }
+        };
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            if let Err(e) = respond(socket, &stats).await {
+                debug!("Failed to answer metrics request: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use url::Url;
+
+    use crate::stats::StoreOutcome;
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("plain"), "plain");
+        assert_eq!(escape_label("a\\b \"c\"\nd"), "a\\\\b \\\"c\\\"\\nd");
+    }
+
+    #[test]
+    fn snapshot_is_rendered_as_prometheus_text() {
+        let stats = CrawlStats::default();
+        let url = Url::parse("https://example.com/").unwrap();
+        let _request = stats.request_started();
+        stats.record_fetch(&url, 200, 100, Duration::from_millis(20));
+        stats.record_fetch(&url, 404, 10, Duration::from_millis(700));
+        stats.record_page(&url, 200);
+        stats.record_results("/scraper/\"title\"", 3);
+        stats.record_store("csv", StoreOutcome::Written(42));
+
+        let text = render(&stats);
+        let lines = text.lines().collect::<Vec<&str>>();
+        for expected in [
+            "# HELP crawler_in_flight_requests HTTP requests waiting for a response.",
+            "# TYPE crawler_in_flight_requests gauge",
+            "crawler_in_flight_requests 1",
+            "# TYPE crawler_pages_total counter",
+            "crawler_pages_total{outcome=\"done\"} 1",
+            "crawler_requests_total{host=\"example.com\"} 2",
+            "crawler_responses_total{status=\"404\"} 1",
+            "# TYPE crawler_request_duration_seconds histogram",
+            "crawler_request_duration_seconds_bucket{le=\"0.01\"} 0",
+            "crawler_request_duration_seconds_bucket{le=\"0.025\"} 1",
+            "crawler_request_duration_seconds_bucket{le=\"0.5\"} 1",
+            "crawler_request_duration_seconds_bucket{le=\"1\"} 2",
+            "crawler_request_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "crawler_request_duration_seconds_sum 0.72",
+            "crawler_request_duration_seconds_count 2",
+            "crawler_step_results_total{step=\"/scraper/\\\"title\\\"\"} 3",
+            "crawler_stored_total{backend=\"csv\",outcome=\"written\"} 1",
+            "crawler_stored_bytes_total{backend=\"csv\"} 42",
+        ] {
+            assert!(lines.contains(&expected), "Missing {:?} in:\n{}", expected, text);
+        }
+        // Every sample line belongs to a family announced before it
+        let mut families = vec![];
+        for line in &lines {
+            match line.strip_prefix("# TYPE ") {
+                Some(family) => families.push(family.split(' ').next().unwrap()),
+                None if !line.starts_with('#') => {
+                    let name = line.split(['{', ' ']).next().unwrap();
+                    assert!(families.iter().any(|family| name.starts_with(family)), "{}", line);
+                }
+                None => {}
+            }
+        }
+    }
+}
@@ -1,19 +1,31 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::result::Result;
 use std::vec;
 
 
+use log::debug;
 use regex::Regex;
 use reqwest::Url;
 use scraper::{Html, Selector};
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use serde_json::{Value};
+use jsonpath_lib::Compiled as JsonPath;
 
 
 
 use crate::errors::ProcessorError;
+use crate::incremental::OnlyWhen;
+use crate::processor::CustomStep;
 use crate::scraper_job::ScraperJob;
+use crate::sink::Sinks;
+use crate::stats::CrawlStats;
 use crate::response_adaptor::Resp;
-use crate::storage::Storage;
+use crate::record::{extract_records, Record, RecordContainer, RecordField};
+use crate::script_json::{find_script_json, ScriptLocator};
+use crate::storage::{de_checked_storage, Storage};
+use crate::xpath::{de_xpath, select_strings, XPathExpr};
 
 pub type ProcessingResult = Result<FinishedProcessingResult, ProcessorError>;
 
@@ -32,11 +44,179 @@ pub enum JSONProcessingResultUnit {
     FormParameter(String),
 }
 
-#[derive(std::fmt::Debug)]
+#[derive(std::fmt::Debug, Clone)]
 pub enum ProcessingResultUnit {
     URL(Url),
     Str(String),
     FormParameter {name: String, value: String},
+    Record(Record),
+}
+
+impl ProcessingResultUnit {
+    pub fn to_json_value(&self) -> Value {
+        match self {
+            ProcessingResultUnit::URL(url) => Value::String(url.to_string()),
+            ProcessingResultUnit::Str(text) => Value::String(text.clone()),
+            ProcessingResultUnit::FormParameter { name, value } => {
+                let mut form_param = Record::new();
+                form_param.insert(name.clone(), Value::String(value.clone()));
+                Value::Object(form_param)
+            }
+            ProcessingResultUnit::Record(record) => Value::Object(record.clone()),
+        }
+    }
+}
+
+// Where a result came from: the fetched URL and the JSON pointer of the step in the config.
+// `parent_urls` are the pages that led to `source_url`, the start URL first,
+// `produced_by` is the JSON pointer of the Scrape or Process step that produced the response or result,
+// `vars` holds values captured up the chain (`param.<name>`, `capture.<field>`) for storage templates,
+// `params` holds only the dynamic parameter and form parameter values up the chain,
+// `job_id` and `depth` identify the ScraperJob run that fetched the URL in logs,
+// `stats` counts what the steps below produce and store,
+// `sinks` are the in-process receivers Channel and Callback storages push to,
+// `failed` is set when a step or storage below the nearest OnlyWhen fails
+#[derive(Debug, Clone)]
+pub struct ResultContext {
+    pub source_url: Url,
+    pub parent_urls: Vec<Url>,
+    pub produced_by: String,
+    pub step_path: String,
+    pub vars: BTreeMap<String, String>,
+    pub params: BTreeMap<String, String>,
+    pub job_id: String,
+    pub depth: usize,
+    pub stats: Arc<CrawlStats>,
+    pub sinks: Sinks,
+    pub failed: Arc<AtomicBool>,
+}
+
+impl ResultContext {
+    pub fn new(source_url: Url, step_path: String) -> Self {
+        ResultContext {
+            source_url,
+            parent_urls: Vec::new(),
+            produced_by: String::new(),
+            step_path,
+            vars: BTreeMap::new(),
+            params: BTreeMap::new(),
+            job_id: String::new(),
+            depth: 0,
+            stats: Arc::default(),
+            sinks: Sinks::default(),
+            failed: Arc::default(),
+        }
+    }
+
+    pub fn with_job(mut self, job_id: &str, depth: usize) -> Self {
+        self.job_id = job_id.to_string();
+        self.depth = depth;
+        self
+    }
+
+    pub fn with_stats(mut self, stats: Arc<CrawlStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn with_sinks(mut self, sinks: Sinks) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    pub fn with_vars(mut self, vars: BTreeMap<String, String>) -> Self {
+        self.vars = vars;
+        self
+    }
+
+    pub fn with_params(mut self, params: BTreeMap<String, String>) -> Self {
+        self.params = params;
+        self
+    }
+
+    // Visible to templates as `{param.<name>}` and recorded in the provenance
+    pub fn with_param(&self, name: &str, value: &str) -> Self {
+        let mut ctx = self.clone();
+        ctx.vars.insert(format!("param.{}", name), value.to_string());
+        ctx.params.insert(name.to_string(), value.to_string());
+        ctx
+    }
+
+    pub fn with_parent_urls(mut self, parent_urls: Vec<Url>) -> Self {
+        self.parent_urls = parent_urls;
+        self
+    }
+
+    pub fn with_producer(mut self, step_path: &str) -> Self {
+        self.produced_by = step_path.to_string();
+        self
+    }
+
+    // Parent URLs of jobs scraping URLs found here
+    pub fn url_chain(&self) -> Vec<Url> {
+        let mut url_chain = self.parent_urls.clone();
+        url_chain.push(self.source_url.clone());
+        url_chain
+    }
+
+    pub fn provenance(&self) -> Provenance {
+        Provenance {
+            step: self.produced_by.clone(),
+            source_url: self.source_url.to_string(),
+            parent_urls: self.parent_urls.iter().map(Url::to_string).collect(),
+            params: self.params.clone(),
+        }
+    }
+
+    // Steps below get their own flag, so an OnlyWhen only sees the failures under it
+    pub fn tracking_failures(&self) -> Self {
+        let mut ctx = self.clone();
+        ctx.failed = Arc::default();
+        ctx
+    }
+
+    pub fn mark_failed(&self) {
+        self.failed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    // A step that captured nothing counts as empty rather than failed, as in the stats
+    pub fn record_step_error(&self, error: &ProcessorError) {
+        self.stats.record_step_error(&self.source_url, &self.step_path, error);
+        if !matches!(error, ProcessorError::NothingToCaptureError) {
+            self.mark_failed();
+        }
+    }
+
+    pub fn child(&self, path_suffix: &str) -> Self {
+        let mut ctx = self.clone();
+        ctx.step_path.push_str(path_suffix);
+        ctx
+    }
+
+    // Record fields stay available to every step below the one processing the record
+    pub fn with_record(&self, record: &Record) -> Self {
+        let mut ctx = self.clone();
+        record.iter().for_each(|(field, value)| {
+            if let Some(text) = json_value_to_string(value) {
+                ctx.vars.insert(format!("capture.{}", field), text);
+            }
+        });
+        ctx
+    }
+}
+
+// What storages write next to a result to trace it back through the config and the crawl
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    pub step: String,
+    pub source_url: String,
+    pub parent_urls: Vec<String>,
+    // Dynamic parameter and form parameter values up the chain
+    pub params: BTreeMap<String, String>,
 }
 
 #[derive(std::fmt::Debug, Deserialize)]
@@ -59,11 +239,128 @@ pub enum LookupBlock {
     Take,
 }
 
+// `lookup_search` or `json_path`, exactly one of them
+#[derive(Debug, Clone)]
+pub enum JsonQuery {
+    Lookup(Vec<LookupBlock>),
+    Path(JsonPath),
+}
+
+#[derive(Deserialize)]
+struct RawJsonQuery {
+    #[serde(default)]
+    lookup_search: Option<Vec<LookupBlock>>,
+    #[serde(default)]
+    #[serde(deserialize_with = "de_json_path")]
+    json_path: Option<JsonPath>,
+}
+
+impl<'de> Deserialize<'de> for JsonQuery {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = RawJsonQuery::deserialize(deserializer)?;
+        match (raw.lookup_search, raw.json_path) {
+            (Some(lookup_search), None) => Ok(JsonQuery::Lookup(lookup_search)),
+            (None, Some(json_path)) => Ok(JsonQuery::Path(json_path)),
+            (Some(_), Some(_)) => Err(de::Error::custom("Set either lookup_search or json_path, not both")),
+            (None, None) => Err(de::Error::custom("Either lookup_search or json_path is required")),
+        }
+    }
+}
+
 #[derive(std::fmt::Debug, Deserialize, Clone)]
 pub enum NextProcessingStep {
     Process(ProcessingStep),
     Scrape(ScraperJob),
-    Store(Storage),
+    Store(#[serde(deserialize_with = "de_checked_storage")] Storage),
+    OnlyWhen(OnlyWhen),
+}
+
+// What a step hands to the steps below it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepInput {
+    Response,
+    URL,
+    Str,
+    FormParameter,
+    Record,
+    // Results of a Custom step, only known at run time
+    Any,
+}
+
+impl StepInput {
+    pub fn of_result(result: &ProcessingResultUnit) -> Self {
+        match result {
+            ProcessingResultUnit::URL(_) => StepInput::URL,
+            ProcessingResultUnit::Str(_) => StepInput::Str,
+            ProcessingResultUnit::FormParameter { .. } => StepInput::FormParameter,
+            ProcessingResultUnit::Record(_) => StepInput::Record,
+        }
+    }
+}
+
+impl NextProcessingStep {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NextProcessingStep::Process(_) => "Process",
+            NextProcessingStep::Scrape(_) => "Scrape",
+            NextProcessingStep::Store(_) => "Store",
+            NextProcessingStep::OnlyWhen(_) => "OnlyWhen",
+        }
+    }
+
+    // Process steps read text or records, Scrape steps fetch URLs
+    pub fn accepts(&self, input: StepInput) -> bool {
+        matches!(
+            (self, input),
+            (NextProcessingStep::Store(_) | NextProcessingStep::OnlyWhen(_), _)
+                | (_, StepInput::Any)
+                | (NextProcessingStep::Process(_), StepInput::Response | StepInput::Str | StepInput::Record)
+                | (NextProcessingStep::Scrape(_), StepInput::URL)
+        )
+    }
+
+    pub fn mismatch(&self, input: StepInput, step_path: &str) -> String {
+        format!("{} step at {} can't take {:?} input", self.name(), step_path, input)
+    }
+}
+
+// One reference token of a JSON pointer, escaped as RFC 6901 requires
+pub fn pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+// Fails on the first step that can't take what the step above hands to it
+pub fn check_steps(steps: &[NextProcessingStep], input: StepInput, path: &str) -> Result<(), String> {
+    for (step_index, step) in steps.iter().enumerate() {
+        let step_path = format!("{}/{}", path, step_index);
+        if !step.accepts(input) {
+            return Err(step.mismatch(input, &step_path));
+        }
+        match step {
+            NextProcessingStep::Process(proc) => {
+                check_steps(proc.next_steps(), proc.output(), &format!("{}/Process/next_steps", step_path))?
+            }
+            NextProcessingStep::Scrape(scraper) => scraper.check_targets(&format!("{}/Scrape", step_path))?,
+            NextProcessingStep::OnlyWhen(only_when) => {
+                only_when.check(input, &step_path)?;
+                check_steps(only_when.next_steps(), input, &format!("{}/OnlyWhen/next_steps", step_path))?
+            }
+            NextProcessingStep::Store(_) => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn has_only_when(steps: &[NextProcessingStep]) -> bool {
+    steps.iter().any(|step| match step {
+        NextProcessingStep::Process(proc) => has_only_when(proc.next_steps()),
+        NextProcessingStep::Scrape(scraper) => scraper.has_only_when(),
+        NextProcessingStep::OnlyWhen(_) => true,
+        NextProcessingStep::Store(_) => false,
+    })
 }
 
 #[derive(std::fmt::Debug, Deserialize, Clone)]
@@ -94,13 +391,35 @@ pub enum ProcessingStep {
         proc_result: JSONProcessingResultUnit,
     },
     JSON {
-        lookup_search: Vec<LookupBlock>,
+        #[serde(flatten)]
+        query: JsonQuery,
+        next_steps: Vec<NextProcessingStep>,
+        proc_result: JSONProcessingResultUnit,
+    },
+    ScriptJSON {
+        script: ScriptLocator,
+        #[serde(flatten)]
+        query: JsonQuery,
+        next_steps: Vec<NextProcessingStep>,
+        proc_result: JSONProcessingResultUnit,
+    },
+    Extract {
+        container: RecordContainer,
+        fields: BTreeMap<String, RecordField>,
         next_steps: Vec<NextProcessingStep>,
+    },
+    XPath {
+        #[serde(deserialize_with = "de_xpath")]
+        xpath: XPathExpr,
+        capture_elements: Capture,
         proc_result: JSONProcessingResultUnit,
+        next_steps: Vec<NextProcessingStep>,
     },
+    // A processor registered with `register_processor`
+    Custom(CustomStep),
 }
 
-fn de_selector<'de, D>(deserializer: D) -> Result<Selector, D::Error>
+pub fn de_selector<'de, D>(deserializer: D) -> Result<Selector, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -126,10 +445,34 @@ where
     })
 }
 
+pub fn de_json_path<'de, D>(deserializer: D) -> Result<Option<JsonPath>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw_path_string: String = Deserialize::deserialize(deserializer)?;
+    JsonPath::compile(&raw_path_string).map(Some).map_err(|_| {
+        de::Error::invalid_value(
+            de::Unexpected::Str(&format!("Invalid JSONPath expression {:?}.", raw_path_string)),
+            &r#""JSONPath expression, e.g. $.items[?(@.price > 100)].url""#,
+        )
+    })
+}
+
+fn json_value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Bool(_) | Value::Number(_) => Some(value.to_string()),
+        // Nested structures are passed on as JSON text, so next steps can keep processing them
+        Value::Array(_) | Value::Object(_) => Some(value.to_string()),
+    }
+}
+
 impl ProcessingStep {
     pub fn process(&self, resp: &Resp) -> ProcessingResult {
-        match resp {
-            Resp::RespText(text) => self.process_string_result(text),
+        match (resp, self) {
+            (Resp::RespText(text), _) => self.process_string_result(text),
+            (Resp::RespBytes { bts, .. }, ProcessingStep::Custom(custom)) => custom.process_bytes(bts),
             _ => { unreachable!("For now") }
         }
     }
@@ -151,24 +494,40 @@ impl ProcessingStep {
                 next_steps: _,
             } => self.process_from_regex(text, regex, groups, capture_elements, proc_result),
             ProcessingStep::JSON {
-                lookup_search,
+                query,
+                next_steps: _,
+                proc_result,
+            } => self.process_from_json(text, query, proc_result),
+            ProcessingStep::ScriptJSON {
+                script,
+                query,
                 next_steps: _,
                 proc_result,
-            } => self.process_from_json(text, lookup_search, proc_result)
+            } => self.process_from_script_json(text, script, query, proc_result),
+            ProcessingStep::Extract {
+                container,
+                fields,
+                next_steps: _,
+            } => self.process_extract(text, container, fields),
+            ProcessingStep::XPath {
+                xpath,
+                capture_elements,
+                proc_result,
+                next_steps: _,
+            } => self.process_from_xpath(text, xpath, capture_elements, proc_result),
+            ProcessingStep::Custom(custom) => custom.process_text(text),
         }
     }
 
+    // Captured text that doesn't make a valid URL fails the step instead of the crawl
     fn string_to_processing_result(
         &self,
         string_result: String,
         proc_result_unit: &JSONProcessingResultUnit,
-    ) -> ProcessingResultUnit {
-        match proc_result_unit {
+    ) -> Result<ProcessingResultUnit, ProcessorError> {
+        let result = match proc_result_unit {
             JSONProcessingResultUnit::PartialURL(_url) => {
-                let new_url = _url.join(&string_result).unwrap_or_else(|_| panic!("FAILED to JOIN URL {:?} and {:?}",
-                        _url.as_str(),
-                        string_result.as_str()));
-                ProcessingResultUnit::URL(new_url)
+                ProcessingResultUnit::URL(_url.join(&string_result)?)
             }
             JSONProcessingResultUnit::Parameter(url, param_name) => {
                 let mut new_url = url.clone();
@@ -178,20 +537,19 @@ impl ProcessingStep {
                 ProcessingResultUnit::URL(new_url)
             }
             JSONProcessingResultUnit::URL => {
-                println!("NEW URL {}", string_result);
-                let new_url = Url::parse(string_result.as_str())
-                    .unwrap_or_else(|_| panic!("Failed to create URL from {} ", string_result));
-                ProcessingResultUnit::URL(new_url)
+                debug!("NEW URL {}", string_result);
+                ProcessingResultUnit::URL(Url::parse(string_result.as_str())?)
             },
             JSONProcessingResultUnit::FormParameter(name) => {
                 ProcessingResultUnit::FormParameter {name: name.clone(), value: string_result}
             },
             JSONProcessingResultUnit::Base(url_base) => {
                 let new_url = url_base.clone() + &string_result;
-                ProcessingResultUnit::URL(Url::parse(&new_url).unwrap())
+                ProcessingResultUnit::URL(Url::parse(&new_url)?)
             },
             _ => ProcessingResultUnit::Str(string_result),
-        }
+        };
+        Ok(result)
     }
 
     pub fn process_from_html(
@@ -225,7 +583,32 @@ impl ProcessingStep {
             })
             .filter_map(|selected_text| selected_text.ok())
             .map(|str_element| self.string_to_processing_result(str_element, proc_result_unit))
-            .collect::<Vec<ProcessingResultUnit>>();
+            .collect::<Result<Vec<ProcessingResultUnit>, ProcessorError>>()?;
+
+        if vec_parsed.is_empty() {
+            Err(ProcessorError::NothingToCaptureError)
+        } else {
+            Ok(FinishedProcessingResult::VectorResult(vec_parsed))
+        }
+    }
+
+    pub fn process_from_xpath(
+        &self,
+        html: &str,
+        xpath: &XPathExpr,
+        capture_elements: &Capture,
+        proc_result_unit: &JSONProcessingResultUnit,
+    ) -> ProcessingResult {
+        let selected = select_strings(html, xpath)?.into_iter();
+
+        let mapped = match capture_elements {
+            Capture::Many(n) => selected.take(*n),
+            Capture::All => selected.take(1_000_000)
+        };
+
+        let vec_parsed = mapped
+            .map(|str_element| self.string_to_processing_result(str_element, proc_result_unit))
+            .collect::<Result<Vec<ProcessingResultUnit>, ProcessorError>>()?;
 
         if vec_parsed.is_empty() {
             Err(ProcessorError::NothingToCaptureError)
@@ -244,7 +627,6 @@ impl ProcessingStep {
     ) -> ProcessingResult {
         let mut captures = regex
             .captures_iter(text)
-            .into_iter()
             .filter_map(|capt| -> Option<(&u8, &str)>  {
                 groups
                     .iter()
@@ -262,8 +644,8 @@ impl ProcessingStep {
             });
 
         let capt_vector = match capture {
-            Capture::Many(n) => capture_result.take(*n).collect::<Vec<ProcessingResultUnit>>(),
-            Capture::All => capture_result.collect::<Vec<ProcessingResultUnit>>(),
+            Capture::Many(n) => capture_result.take(*n).collect::<Result<Vec<ProcessingResultUnit>, ProcessorError>>()?,
+            Capture::All => capture_result.collect::<Result<Vec<ProcessingResultUnit>, ProcessorError>>()?,
         };
 
         if capt_vector.is_empty() {
@@ -276,16 +658,26 @@ impl ProcessingStep {
     pub fn process_from_json(
         &self,
         text: &str,
-        lookup_search: &[LookupBlock],
+        query: &JsonQuery,
         proc_result_unit: &JSONProcessingResultUnit,
     ) -> ProcessingResult {
         let mut vec_parsed = vec![];
-        let json_object: Value = serde_json::from_str(text).unwrap();
-        self.parse_json_value(0, &mut vec_parsed, &json_object, lookup_search);
-        let vec_results: Vec<ProcessingResultUnit> = vec_parsed
+        let json_object: Value = serde_json::from_str(text)?;
+        match query {
+            JsonQuery::Path(path) => path
+                .select(&json_object)
+                .map_err(|_| ProcessorError::NothingToCaptureError)?
+                .into_iter()
+                .filter_map(json_value_to_string)
+                .for_each(|found| vec_parsed.push(found)),
+            JsonQuery::Lookup(lookup_search) => {
+                self.parse_json_value(0, &mut vec_parsed, &json_object, lookup_search)?
+            }
+        }
+        let vec_results = vec_parsed
             .into_iter()
             .map(|str_element| self.string_to_processing_result(str_element, proc_result_unit))
-            .collect();
+            .collect::<Result<Vec<ProcessingResultUnit>, ProcessorError>>()?;
 
         if vec_results.is_empty() {
             Err(ProcessorError::NothingToCaptureError)
@@ -294,37 +686,108 @@ impl ProcessingStep {
         }
     }
 
+    pub fn process_from_script_json(
+        &self,
+        html: &str,
+        script: &ScriptLocator,
+        query: &JsonQuery,
+        proc_result_unit: &JSONProcessingResultUnit,
+    ) -> ProcessingResult {
+        let vec_results = find_script_json(html, script)
+            .iter()
+            .filter_map(|json_text| {
+                match self.process_from_json(json_text, query, proc_result_unit) {
+                    Ok(FinishedProcessingResult::VectorResult(results)) => Some(results),
+                    _ => None,
+                }
+            })
+            .flatten()
+            .collect::<Vec<ProcessingResultUnit>>();
+
+        if vec_results.is_empty() {
+            Err(ProcessorError::NothingToCaptureError)
+        } else {
+            Ok(FinishedProcessingResult::VectorResult(vec_results))
+        }
+    }
+
+    pub fn process_extract(
+        &self,
+        text: &str,
+        container: &RecordContainer,
+        fields: &BTreeMap<String, RecordField>,
+    ) -> ProcessingResult {
+        let records = extract_records(text, container, fields)?
+            .into_iter()
+            .map(ProcessingResultUnit::Record)
+            .collect::<Vec<ProcessingResultUnit>>();
+
+        if records.is_empty() {
+            Err(ProcessorError::NothingToCaptureError)
+        } else {
+            Ok(FinishedProcessingResult::VectorResult(records))
+        }
+    }
+
     pub fn parse_json_value(
         &self,
         cur_lookup: usize,
         results_vec: &mut Vec<String>,
         value: &Value,
         lookup_search: &[LookupBlock],
-    ) {
-        // Todo handle Panic + lookup overflow
-        match (&lookup_search[cur_lookup], value) {
+    ) -> Result<(), ProcessorError> {
+        let lookup = lookup_search
+            .get(cur_lookup)
+            .ok_or(ProcessorError::NothingToCaptureError)?;
+
+        match (lookup, value) {
             (LookupBlock::Att(attr), Value::Object(sub_val)) => {
-                let new_value = sub_val.get(attr).unwrap();
-                self.parse_json_value(cur_lookup + 1, results_vec, new_value, lookup_search);
+                let new_value = sub_val
+                    .get(attr)
+                    .ok_or(ProcessorError::NothingToCaptureError)?;
+                self.parse_json_value(cur_lookup + 1, results_vec, new_value, lookup_search)
             }
             (LookupBlock::Pos(ind), Value::Array(sub_values)) => {
-                let new_value = sub_values.get(*ind).unwrap();
-                self.parse_json_value(cur_lookup + 1, results_vec, new_value, lookup_search);
+                let new_value = sub_values
+                    .get(*ind)
+                    .ok_or(ProcessorError::NothingToCaptureError)?;
+                self.parse_json_value(cur_lookup + 1, results_vec, new_value, lookup_search)
             }
             (LookupBlock::Att(_), Value::Array(sub_values)) => {
+                // Array items missing the attribute are skipped, the others are still captured
                 sub_values.iter().for_each(|sub_value| {
-                    self.parse_json_value(cur_lookup, results_vec, sub_value, lookup_search);
-                })
+                    let _ = self.parse_json_value(cur_lookup, results_vec, sub_value, lookup_search);
+                });
+                Ok(())
             }
             (
                 LookupBlock::Take,
                 v @ Value::Bool(_) | v @ Value::Number(_) | v @ Value::String(_),
             ) => {
-                results_vec.push(v.as_str().unwrap().to_string());
-            }
-            (lookup, val) => {
-                panic!("WRONG VALUE TYPE {} for Loookup {:?}", val, lookup);
+                results_vec.extend(json_value_to_string(v));
+                Ok(())
             }
+            _ => Err(ProcessorError::NothingToCaptureError),
+        }
+    }
+
+    pub fn output(&self) -> StepInput {
+        let proc_result = match self {
+            ProcessingStep::Html { proc_result, .. }
+            | ProcessingStep::Regex { proc_result, .. }
+            | ProcessingStep::JSON { proc_result, .. }
+            | ProcessingStep::ScriptJSON { proc_result, .. }
+            | ProcessingStep::XPath { proc_result, .. } => proc_result,
+            ProcessingStep::Extract { .. } => return StepInput::Record,
+            ProcessingStep::Custom(_) => return StepInput::Any,
+        };
+        match proc_result {
+            JSONProcessingResultUnit::Str => StepInput::Str,
+            JSONProcessingResultUnit::FormParameter(_) => StepInput::FormParameter,
+            JSONProcessingResultUnit::URL
+            | JSONProcessingResultUnit::PartialURL(_)
+            | JSONProcessingResultUnit::Base(_)
+            | JSONProcessingResultUnit::Parameter(..) => StepInput::URL,
         }
     }
 
@@ -333,6 +796,78 @@ impl ProcessingStep {
             ProcessingStep::Html { next_steps, .. } => next_steps,
             ProcessingStep::Regex { next_steps, .. } => next_steps,
             ProcessingStep::JSON { next_steps, .. } => next_steps,
+            ProcessingStep::ScriptJSON { next_steps, .. } => next_steps,
+            ProcessingStep::Extract { next_steps, .. } => next_steps,
+            ProcessingStep::XPath { next_steps, .. } => next_steps,
+            ProcessingStep::Custom(custom) => custom.next_steps(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn json_step(query: Value, proc_result: Value) -> Result<ProcessingStep, serde_json::Error> {
+        let mut step = json!({"type": "JSON", "next_steps": [], "proc_result": proc_result});
+        step.as_object_mut().unwrap().extend(query.as_object().unwrap().clone());
+        serde_json::from_value(step)
+    }
+
+    fn captured(step: &ProcessingStep, text: &str) -> Result<Vec<String>, ProcessorError> {
+        match step.process_string_result(text)? {
+            FinishedProcessingResult::VectorResult(results) => Ok(results
+                .iter()
+                .map(|result| match result {
+                    ProcessingResultUnit::URL(url) => url.to_string(),
+                    ProcessingResultUnit::Str(text) => text.clone(),
+                    other => format!("{:?}", other),
+                })
+                .collect()),
+            FinishedProcessingResult::NothingRequired => Ok(vec![]),
         }
     }
+
+    #[test]
+    fn json_steps_need_exactly_one_query() {
+        assert!(json_step(json!({"json_path": "$.a"}), json!("Str")).is_ok());
+        assert!(json_step(json!({"lookup_search": [{"Att": "a"}, "Take"]}), json!("Str")).is_ok());
+        let both = json_step(json!({"json_path": "$.a", "lookup_search": ["Take"]}), json!("Str"));
+        assert!(both.unwrap_err().to_string().contains("not both"));
+        let neither = json_step(json!({}), json!("Str"));
+        assert!(neither.unwrap_err().to_string().contains("is required"));
+    }
+
+    #[test]
+    fn json_path_filters_and_missing_paths_capture_nothing() {
+        let step = json_step(json!({"json_path": "$.items[?(@.price > 100)].url"}), json!("Str")).unwrap();
+        let text = r#"{"items": [{"price": 50, "url": "/a"}, {"price": 150, "url": "/b"}]}"#;
+        assert_eq!(captured(&step, text).unwrap(), vec!["/b"]);
+        assert!(matches!(captured(&step, r#"{"other": 1}"#), Err(ProcessorError::NothingToCaptureError)));
+
+        let lookup = json_step(json!({"lookup_search": [{"Att": "missing"}, "Take"]}), json!("Str")).unwrap();
+        assert!(matches!(captured(&lookup, r#"{"other": 1}"#), Err(ProcessorError::NothingToCaptureError)));
+    }
+
+    #[test]
+    fn values_that_are_not_urls_fail_the_step() {
+        let text = r#"{"links": ["https://example.com/a", "not a url"]}"#;
+        let step = json_step(json!({"json_path": "$.links[*]"}), json!("URL")).unwrap();
+        assert!(matches!(captured(&step, text), Err(ProcessorError::UrlParseError(_))));
+
+        let base = json_step(json!({"json_path": "$.links[1]"}), json!({"Base": "http://"})).unwrap();
+        assert!(matches!(captured(&base, text), Err(ProcessorError::UrlParseError(_))));
+
+        let partial = json_step(json!({"json_path": "$.links[1]"}), json!({"PartialURL": "https://example.com/x/"})).unwrap();
+        assert_eq!(captured(&partial, text).unwrap(), vec!["https://example.com/x/not%20a%20url"]);
+    }
+
+    #[test]
+    fn pointer_tokens_escape_tilde_before_slash() {
+        assert_eq!(pointer_token("Text"), "Text");
+        assert_eq!(pointer_token("a/b~c"), "a~1b~0c");
+        assert_eq!(pointer_token("~1"), "~01");
+    }
 }
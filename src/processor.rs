@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, LazyLock, RwLock};
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::errors::{ProcessorError, RegisterError};
+use crate::parser::{FinishedProcessingResult, NextProcessingStep, ProcessingResult};
+
+// A step type added by the embedding application, e.g. a PDF text extractor
+pub trait Processor: Send + Sync {
+    fn process_text(&self, text: &str) -> ProcessingResult;
+
+    // Downloaded files reach the processor when it runs under a `Bytes` target
+    fn process_bytes(&self, _bytes: &[u8]) -> ProcessingResult {
+        Err(ProcessorError::CustomProcessorError(
+            "Processor does not take downloaded files".to_string(),
+        ))
+    }
+}
+
+// Builds a processor from the `options` of a step
+pub type ProcessorFactory = dyn Fn(&Value) -> Result<Box<dyn Processor>, String> + Send + Sync;
+
+static PROCESSORS: LazyLock<RwLock<BTreeMap<String, Arc<ProcessorFactory>>>> =
+    LazyLock::new(|| RwLock::new(BTreeMap::new()));
+
+// Processors have to be registered before a config using them is loaded.
+// A name can only be registered once, so one part of the application can't swap out another's processor
+pub fn register_processor<F>(name: &str, factory: F) -> Result<(), RegisterError>
+where
+    F: Fn(&Value) -> Result<Box<dyn Processor>, String> + Send + Sync + 'static,
+{
+    let mut processors = PROCESSORS.write().expect("Processor registry lock is poisoned");
+    if processors.contains_key(name) {
+        return Err(RegisterError::DuplicateName(name.to_string()));
+    }
+    processors.insert(name.to_string(), Arc::new(factory));
+    Ok(())
+}
+
+fn build_processor(name: &str, options: &Value) -> Result<Box<dyn Processor>, String> {
+    let factory = PROCESSORS
+        .read()
+        .expect("Processor registry lock is poisoned")
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("No processor named {:?} is registered", name))?;
+    factory(options).map_err(|e| format!("Invalid options for processor {:?}: {}", name, e))
+}
+
+#[derive(Deserialize)]
+pub struct PlainCustomStep {
+    name: String,
+    #[serde(default)]
+    options: Value,
+    #[serde(default)]
+    next_steps: Vec<NextProcessingStep>,
+}
+
+// `{"type": "Custom", "name": "...", "options": {...}, "next_steps": [...]}`
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "PlainCustomStep")]
+pub struct CustomStep {
+    name: String,
+    processor: Arc<dyn Processor>,
+    next_steps: Vec<NextProcessingStep>,
+}
+
+impl TryFrom<PlainCustomStep> for CustomStep {
+    type Error = String;
+
+    fn try_from(step: PlainCustomStep) -> Result<Self, Self::Error> {
+        Ok(CustomStep {
+            processor: build_processor(&step.name, &step.options)?.into(),
+            name: step.name,
+            next_steps: step.next_steps,
+        })
+    }
+}
+
+impl Debug for CustomStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CustomStep")
+            .field("name", &self.name)
+            .field("next_steps", &self.next_steps)
+            .finish()
+    }
+}
+
+// Like the built-in steps, finding nothing is an error
+fn non_empty(result: ProcessingResult) -> ProcessingResult {
+    match result? {
+        FinishedProcessingResult::VectorResult(results) if results.is_empty() => {
+            Err(ProcessorError::NothingToCaptureError)
+        }
+        finished => Ok(finished),
+    }
+}
+
+impl CustomStep {
+    pub fn process_text(&self, text: &str) -> ProcessingResult {
+        non_empty(self.processor.process_text(text))
+    }
+
+    pub fn process_bytes(&self, bytes: &[u8]) -> ProcessingResult {
+        non_empty(self.processor.process_bytes(bytes))
+    }
+
+    pub fn next_steps(&self) -> &Vec<NextProcessingStep> {
+        &self.next_steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::parser::ProcessingResultUnit;
+
+    struct Fixed(&'static str);
+
+    impl Processor for Fixed {
+        fn process_text(&self, _text: &str) -> ProcessingResult {
+            Ok(FinishedProcessingResult::VectorResult(vec![ProcessingResultUnit::Str(self.0.to_string())]))
+        }
+    }
+
+    fn custom_step(name: &str) -> Value {
+        json!({"type": "Custom", "name": name, "next_steps": []})
+    }
+
+    fn processed(step: &CustomStep) -> String {
+        match step.process_text("") {
+            Ok(FinishedProcessingResult::VectorResult(results)) => match &results[..] {
+                [ProcessingResultUnit::Str(text)] => text.clone(),
+                other => panic!("Unexpected results {:?}", other),
+            },
+            Ok(FinishedProcessingResult::NothingRequired) => panic!("Expected results"),
+            Err(e) => panic!("Processing failed: {}", e),
+        }
+    }
+
+    #[test]
+    fn unknown_processor_fails_at_config_load() {
+        let step = serde_json::from_value::<CustomStep>(custom_step("never_registered_test"));
+        assert!(step.unwrap_err().to_string().contains("No processor named \"never_registered_test\" is registered"));
+
+        let config = json!({"scraper": {"targets": {"Text": [{"Process": custom_step("never_registered_test")}]}}, "urls": []});
+        assert!(crate::Crawler::from_value(config).is_err());
+    }
+
+    #[test]
+    fn rejected_options_fail_at_config_load() {
+        register_processor("needs_options_test", |options| match options["min_len"].as_u64() {
+            Some(_) => Ok(Box::new(Fixed("ok"))),
+            None => Err("min_len is required".to_string()),
+        })
+        .unwrap();
+        let step = serde_json::from_value::<CustomStep>(custom_step("needs_options_test"));
+        assert!(step.unwrap_err().to_string().contains("min_len is required"));
+    }
+
+    #[test]
+    fn a_name_is_registered_only_once() {
+        register_processor("registered_twice_test", |_| Ok(Box::new(Fixed("first")))).unwrap();
+        let again = register_processor("registered_twice_test", |_| Ok(Box::new(Fixed("second"))));
+        assert!(matches!(again, Err(RegisterError::DuplicateName(name)) if name == "registered_twice_test"));
+
+        let step: CustomStep = serde_json::from_value(custom_step("registered_twice_test")).unwrap();
+        assert_eq!(processed(&step), "first");
+    }
+}
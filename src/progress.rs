@@ -0,0 +1,207 @@
+use std::io::{stdout, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::info;
+use serde::Deserialize;
+use tokio::task::JoinHandle;
+
+use crate::stats::{human_bytes, CrawlStats, StatsSnapshot};
+
+// How often the live view is redrawn
+const REFRESH: Duration = Duration::from_millis(250);
+// Throughput of the live view is averaged over at least this long
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+// Longer lines would wrap and break the redraw
+const MAX_LINE_CHARS: usize = 120;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProgressSettings {
+    // Seconds between summary lines when stdout is not a terminal
+    #[serde(default = "default_interval_secs")]
+    interval_secs: u64,
+}
+
+fn default_interval_secs() -> u64 {
+    10
+}
+
+struct Frame {
+    stats: Arc<CrawlStats>,
+    live: bool,
+    // Lines of the last frame, cleared before the next one is drawn
+    drawn_lines: usize,
+    previous_pages: u64,
+    previous_bytes: u64,
+    previous_at: Instant,
+}
+
+fn truncate(line: String) -> String {
+    match line.char_indices().nth(MAX_LINE_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line,
+    }
+}
+
+impl Frame {
+    fn new(stats: Arc<CrawlStats>, live: bool) -> Self {
+        Frame {
+            stats,
+            live,
+            drawn_lines: 0,
+            previous_pages: 0,
+            previous_bytes: 0,
+            previous_at: Instant::now(),
+        }
+    }
+
+    // Pages and bytes per second since the start of the current window
+    fn throughput(&mut self, data: &StatsSnapshot) -> (f64, f64) {
+        let pages = data.pages_done + data.pages_failed;
+        let elapsed = self.previous_at.elapsed();
+        let secs = elapsed.as_secs_f64().max(1.0);
+        let rates = (
+            (pages - self.previous_pages) as f64 / secs,
+            (data.bytes_downloaded - self.previous_bytes) as f64 / secs,
+        );
+        if !self.live || elapsed >= THROUGHPUT_WINDOW {
+            self.previous_pages = pages;
+            self.previous_bytes = data.bytes_downloaded;
+            self.previous_at = Instant::now();
+        }
+        rates
+    }
+
+    fn summary(&mut self, data: &StatsSnapshot) -> String {
+        let (pages_per_sec, bytes_per_sec) = self.throughput(data);
+        format!(
+            "{} done, {} failed, {} queued, {} running, depth {} (max {}), {:.1} pages/s, {}/s",
+            data.pages_done,
+            data.pages_failed,
+            data.queued_jobs,
+            data.running_jobs,
+            // Deepest job running right now
+            data.running_depths.keys().next_back().copied().unwrap_or(0),
+            data.max_depth,
+            pages_per_sec,
+            human_bytes(bytes_per_sec as u64)
+        )
+    }
+
+    fn draw(&mut self) {
+        let data = self.stats.snapshot();
+        let summary = self.summary(&data);
+        if !self.live {
+            info!("Progress: {}", summary);
+            return;
+        }
+
+        let mut lines = vec![
+            format!("Pages: {}", summary),
+            format!(
+                "Requests: {} sent, {} in flight, {} downloaded in {:.0} s",
+                data.requests,
+                data.in_flight_requests,
+                human_bytes(data.bytes_downloaded),
+                self.stats.elapsed().as_secs_f64()
+            ),
+        ];
+        if !data.recent_errors.is_empty() {
+            lines.push("Recent errors:".to_string());
+            lines.extend(data.recent_errors.iter().map(|error| format!("  {}", error)));
+        }
+
+        let mut out = stdout().lock();
+        if self.drawn_lines > 0 {
+            // Back to the first line of the previous frame, then clear everything below it
+            let _ = write!(out, "\x1b[{}F\x1b[J", self.drawn_lines);
+        }
+        for line in &lines {
+            let _ = writeln!(out, "{}", truncate(line.clone()));
+        }
+        let _ = out.flush();
+        self.drawn_lines = lines.len();
+    }
+}
+
+pub struct ProgressView {
+    frame: Arc<Mutex<Frame>>,
+    task: JoinHandle<()>,
+}
+
+// The live view needs a terminal; anywhere else progress is logged as summary lines
+pub fn is_live() -> bool {
+    stdout().is_terminal()
+}
+
+impl ProgressView {
+    pub fn start(settings: &ProgressSettings, stats: Arc<CrawlStats>) -> Self {
+        let live = is_live();
+        let frame = Arc::new(Mutex::new(Frame::new(stats, live)));
+        let period = match live {
+            true => REFRESH,
+            false => Duration::from_secs(settings.interval_secs.max(1)),
+        };
+        let task_frame = frame.clone();
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // The first tick completes at once, there is nothing to show yet
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                task_frame.lock().expect("Progress lock is poisoned").draw();
+            }
+        });
+        ProgressView { frame, task }
+    }
+
+    // Leaves the final numbers on screen once every job has finished
+    pub fn finish(self) {
+        self.task.abort();
+        let mut frame = self.frame.lock().expect("Progress lock is poisoned");
+        if frame.live {
+            // Throughput of the last frame is averaged over the whole run
+            let elapsed = frame.stats.elapsed();
+            frame.previous_pages = 0;
+            frame.previous_bytes = 0;
+            frame.previous_at = Instant::now().checked_sub(elapsed).unwrap_or(frame.previous_at);
+            frame.draw();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    #[test]
+    fn logged_progress_line_counts_since_the_previous_line() {
+        let stats = Arc::new(CrawlStats::default());
+        let url = Url::parse("https://example.com/").unwrap();
+        let _job = stats.job_started(2);
+        for status in [200, 200, 301, 500] {
+            stats.record_page(&url, status);
+        }
+        stats.record_fetch(&url, 200, 2048, Duration::from_millis(20));
+
+        let mut frame = Frame::new(stats.clone(), false);
+        assert_eq!(
+            frame.summary(&stats.snapshot()),
+            "3 done, 1 failed, 0 queued, 1 running, depth 2 (max 2), 4.0 pages/s, 2.0 KiB/s"
+        );
+        // Without a terminal every line starts a new window
+        assert_eq!(
+            frame.summary(&stats.snapshot()),
+            "3 done, 1 failed, 0 queued, 1 running, depth 2 (max 2), 0.0 pages/s, 0 B/s"
+        );
+    }
+
+    #[test]
+    fn long_lines_are_truncated() {
+        assert_eq!(truncate("short".to_string()), "short");
+        let line = truncate("x".repeat(MAX_LINE_CHARS + 10));
+        assert_eq!(line.len(), MAX_LINE_CHARS + 3);
+        assert!(line.ends_with("x..."));
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeMap;
+
+use jsonpath_lib::Compiled as JsonPath;
+use scraper::{ElementRef, Html, Selector};
+use serde::{de, Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use url::Url;
+
+use crate::errors::ProcessorError;
+use crate::parser::{de_json_path, de_selector, SelectorTarget};
+
+pub type Record = Map<String, Value>;
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum RecordContainer {
+    Html(#[serde(deserialize_with = "de_selector")] Selector),
+    JSON(#[serde(deserialize_with = "de_required_json_path")] JsonPath),
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RecordField {
+    #[serde(default)]
+    #[serde(deserialize_with = "de_optional_selector")]
+    selector: Option<Selector>,
+    #[serde(default = "default_selector_target")]
+    selector_target: SelectorTarget,
+    #[serde(default)]
+    #[serde(deserialize_with = "de_json_path")]
+    json_path: Option<JsonPath>,
+    #[serde(default)]
+    join_url: Option<Url>,
+    #[serde(default)]
+    many: bool,
+}
+
+fn default_selector_target() -> SelectorTarget {
+    SelectorTarget::Text
+}
+
+fn de_optional_selector<'de, D>(deserializer: D) -> Result<Option<Selector>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    de_selector(deserializer).map(Some)
+}
+
+fn de_required_json_path<'de, D>(deserializer: D) -> Result<JsonPath, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    de_json_path(deserializer)?.ok_or_else(|| de::Error::custom("JSONPath is required"))
+}
+
+impl RecordField {
+    fn finish(&self, values: Vec<String>) -> Value {
+        let mut values = values
+            .into_iter()
+            .map(|value| match &self.join_url {
+                Some(base) => base.join(&value).map(String::from).unwrap_or(value),
+                None => value,
+            })
+            .map(Value::String)
+            .collect::<Vec<Value>>();
+
+        if self.many {
+            Value::Array(values)
+        } else if values.is_empty() {
+            Value::Null
+        } else {
+            values.swap_remove(0)
+        }
+    }
+
+    fn value_in_html(&self, container: &ElementRef) -> Value {
+        let selected = match &self.selector {
+            Some(selector) => container.select(selector).collect::<Vec<ElementRef>>(),
+            None => vec![*container],
+        };
+        let values = selected
+            .iter()
+            .filter_map(|element| match &self.selector_target {
+                SelectorTarget::Attr(attr) => element.value().attr(attr).map(str::to_owned),
+                SelectorTarget::Text => Some(element.text().collect::<String>().trim().to_owned()),
+            })
+            .collect();
+        self.finish(values)
+    }
+
+    fn value_in_json(&self, container: &Value) -> Value {
+        let values = match &self.json_path {
+            Some(path) => path.select(container).unwrap_or_default(),
+            None => vec![container],
+        };
+        self.finish(values.into_iter().filter_map(json_scalar_to_string).collect())
+    }
+}
+
+fn json_scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Bool(_) | Value::Number(_) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+pub fn extract_records(
+    text: &str,
+    container: &RecordContainer,
+    fields: &BTreeMap<String, RecordField>,
+) -> Result<Vec<Record>, ProcessorError> {
+    match container {
+        RecordContainer::Html(selector) => {
+            let document_tree = Html::parse_document(text);
+            let records = document_tree
+                .select(selector)
+                .map(|element| {
+                    fields
+                        .iter()
+                        .map(|(name, field)| (name.clone(), field.value_in_html(&element)))
+                        .collect::<Record>()
+                })
+                .collect();
+            Ok(records)
+        }
+        RecordContainer::JSON(path) => {
+            let json_object: Value = serde_json::from_str(text)?;
+            let records = path
+                .select(&json_object)
+                .map_err(|_| ProcessorError::NothingToCaptureError)?
+                .into_iter()
+                .map(|object| {
+                    fields
+                        .iter()
+                        .map(|(name, field)| (name.clone(), field.value_in_json(object)))
+                        .collect::<Record>()
+                })
+                .collect();
+            Ok(records)
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::fs::{read, read_dir, File};
+use std::io::{copy, sink, BufRead, BufReader, Read, Result as IOResult, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bytes::Bytes;
+use flate2::bufread::GzDecoder;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{StatusCode, Version};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::response_adaptor::FetchedResponse;
+use crate::storage::sha256_hex;
+
+// Where previously fetched responses are served from instead of the network
+#[derive(Debug, Deserialize, Clone)]
+pub enum ReplaySource {
+    Warc(PathBuf),
+    Directory(PathBuf),
+}
+
+impl FromStr for ReplaySource {
+    type Err = String;
+
+    // warc:archive/ or dir:http_cache/
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source.split_once(':') {
+            Some(("warc", path)) => Ok(ReplaySource::Warc(PathBuf::from(path))),
+            Some(("dir", path)) => Ok(ReplaySource::Directory(PathBuf::from(path))),
+            _ => Err(format!(
+                "Unknown replay source {:?}, expected warc:<path> or dir:<path>",
+                source
+            )),
+        }
+    }
+}
+
+// `offset` is where the record (or the gzip member holding it) starts in the file,
+// `record_offset` where the record starts in the decompressed member
+#[derive(Debug, Clone)]
+pub struct WarcLocation {
+    path: PathBuf,
+    offset: u64,
+    gzip: bool,
+    record_offset: u64,
+}
+
+#[derive(Debug)]
+pub enum ReplayArchive {
+    Warc(HashMap<String, WarcLocation>),
+    Directory(PathBuf),
+}
+
+// Entry of a directory archive: <sha256 of the URL>.json next to <sha256 of the URL>.body
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DirectoryEntry {
+    pub url: Url,
+    pub status: u16,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    // Unix seconds of the last fetch or revalidation, written by the HTTP cache
+    #[serde(default)]
+    pub stored_at: Option<i64>,
+}
+
+impl DirectoryEntry {
+    pub fn to_response(&self, body: Bytes) -> FetchedResponse {
+        FetchedResponse {
+            url: self.url.clone(),
+            status: StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK),
+            version: Version::HTTP_11,
+            headers: to_header_map(&self.headers),
+            body,
+        }
+    }
+}
+
+pub fn directory_key(url: &Url) -> String {
+    sha256_hex(url.as_str().as_bytes())
+}
+
+pub fn to_header_map(headers: &[(String, String)]) -> HeaderMap {
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            header_map.append(name, value);
+        }
+    }
+    header_map
+}
+
+fn find_blank_line(data: &[u8]) -> Option<usize> {
+    data.windows(4).position(|window| window == b"\r\n\r\n")
+}
+
+fn parse_fields(head: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(head)
+        .split("\r\n")
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+// Reads up to the blank line ending the next record head, skipping the blank lines between records.
+// Returns the lower-cased fields and the position the record starts at, None at the end of the stream
+fn read_record_head(
+    reader: &mut impl BufRead,
+    position: &mut u64,
+) -> IOResult<Option<(HashMap<String, String>, u64)>> {
+    let mut head = vec![];
+    let mut record_start = *position;
+    loop {
+        let mut line = vec![];
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        *position += read as u64;
+        if line == b"\r\n" || line == b"\n" {
+            if head.is_empty() {
+                record_start = *position;
+                continue;
+            }
+            break;
+        }
+        head.extend_from_slice(&line);
+    }
+    let fields = parse_fields(&head)
+        .into_iter()
+        .map(|(name, value)| (name.to_lowercase(), value))
+        .collect();
+    Ok(Some((fields, record_start)))
+}
+
+fn content_length(fields: &HashMap<String, String>) -> Option<u64> {
+    fields.get("content-length")?.parse().ok()
+}
+
+// Calls `found` with the fields and the start position of every record in the stream,
+// skipping the blocks instead of reading them into memory
+fn scan_records(reader: &mut impl BufRead, mut found: impl FnMut(HashMap<String, String>, u64)) -> IOResult<()> {
+    let mut position = 0;
+    while let Some((fields, record_start)) = read_record_head(reader, &mut position)? {
+        let block_len = match content_length(&fields) {
+            Some(block_len) => block_len,
+            None => break,
+        };
+        let skipped = copy(&mut reader.take(block_len), &mut sink())?;
+        position += skipped;
+        // A truncated last record is left out
+        if skipped < block_len {
+            break;
+        }
+        found(fields, record_start);
+    }
+    Ok(())
+}
+
+fn parse_http_response(url: Url, block: &[u8]) -> Option<FetchedResponse> {
+    let head_end = find_blank_line(block)?;
+    let head = String::from_utf8_lossy(&block[..head_end]);
+    let mut lines = head.split("\r\n");
+    let mut status_line = lines.next()?.split(' ');
+    let version = match status_line.next()? {
+        "HTTP/0.9" => Version::HTTP_09,
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/2" | "HTTP/2.0" => Version::HTTP_2,
+        "HTTP/3" => Version::HTTP_3,
+        _ => Version::HTTP_11,
+    };
+    let status = StatusCode::from_u16(status_line.next()?.parse().ok()?).ok()?;
+    let headers = to_header_map(&parse_fields(&block[..head_end]));
+    Some(FetchedResponse {
+        url,
+        status,
+        version,
+        headers,
+        body: Bytes::copy_from_slice(&block[head_end + 4..]),
+    })
+}
+
+#[derive(Default)]
+struct WarcIndex {
+    responses: HashMap<String, String>,
+    locations: HashMap<String, WarcLocation>,
+    requests: Vec<(String, String)>,
+}
+
+impl WarcIndex {
+    fn add(&mut self, fields: &HashMap<String, String>, location: WarcLocation) {
+        let field = |name: &str| fields.get(name).cloned();
+        match (field("warc-type").as_deref(), field("warc-target-uri")) {
+            (Some("response"), Some(url)) => {
+                if let Some(record_id) = field("warc-record-id") {
+                    self.locations.insert(record_id.clone(), location);
+                    // Later captures of a URL win
+                    self.responses.insert(url, record_id);
+                }
+            }
+            (Some("request"), Some(url)) => {
+                if let Some(response_id) = field("warc-concurrent-to") {
+                    self.requests.push((url, response_id));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Streams the file, so indexing never holds more than one record head in memory
+    fn scan_file(&mut self, path: &Path) -> IOResult<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+        if !gzip {
+            return scan_records(&mut reader, |fields, record_start| {
+                let location = WarcLocation {
+                    path: path.to_owned(),
+                    offset: record_start,
+                    gzip,
+                    record_offset: 0,
+                };
+                self.add(&fields, location);
+            });
+        }
+
+        // The decoder takes exactly one gzip member from the reader, so its position is the next member's offset
+        while !reader.fill_buf()?.is_empty() {
+            let member_offset = reader.stream_position()?;
+            let mut member = BufReader::new(GzDecoder::new(&mut reader));
+            scan_records(&mut member, |fields, record_offset| {
+                let location = WarcLocation {
+                    path: path.to_owned(),
+                    offset: member_offset,
+                    gzip,
+                    record_offset,
+                };
+                self.add(&fields, location);
+            })?;
+            copy(&mut member, &mut sink())?;
+        }
+        Ok(())
+    }
+
+    // Requests are looked up by the URL they asked for, which differs from the response URL after redirects
+    fn into_locations(self) -> HashMap<String, WarcLocation> {
+        let mut by_url = HashMap::new();
+        for (url, record_id) in &self.responses {
+            if let Some(location) = self.locations.get(record_id) {
+                by_url.insert(url.clone(), location.clone());
+            }
+        }
+        for (url, response_id) in &self.requests {
+            if let Some(location) = self.locations.get(response_id) {
+                by_url.insert(url.clone(), location.clone());
+            }
+        }
+        by_url
+    }
+}
+
+fn warc_files(path: &Path) -> IOResult<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_owned()]);
+    }
+    let mut files = read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| {
+            let name = file.to_string_lossy();
+            name.ends_with(".warc.gz") || name.ends_with(".warc")
+        })
+        .collect::<Vec<PathBuf>>();
+    // File names start with the crawl time, so newer captures are indexed last
+    files.sort();
+    Ok(files)
+}
+
+impl ReplayArchive {
+    pub fn open(source: &ReplaySource) -> IOResult<Self> {
+        match source {
+            ReplaySource::Warc(path) => {
+                let mut index = WarcIndex::default();
+                for file in warc_files(path)? {
+                    index.scan_file(&file)?;
+                }
+                Ok(ReplayArchive::Warc(index.into_locations()))
+            }
+            ReplaySource::Directory(dir) => Ok(ReplayArchive::Directory(dir.clone())),
+        }
+    }
+
+    pub fn indexed_urls(&self) -> Option<usize> {
+        match self {
+            ReplayArchive::Warc(locations) => Some(locations.len()),
+            ReplayArchive::Directory(_) => None,
+        }
+    }
+
+    pub fn lookup(&self, url: &Url) -> IOResult<Option<FetchedResponse>> {
+        match self {
+            ReplayArchive::Warc(locations) => match locations.get(url.as_str()) {
+                Some(location) => read_warc_response(location),
+                None => Ok(None),
+            },
+            ReplayArchive::Directory(dir) => Ok(read_directory_entry(dir, url)?
+                .map(|(entry, body)| entry.to_response(body))),
+        }
+    }
+}
+
+fn read_warc_response(location: &WarcLocation) -> IOResult<Option<FetchedResponse>> {
+    let mut file = File::open(&location.path)?;
+    file.seek(SeekFrom::Start(location.offset))?;
+    let mut reader: Box<dyn BufRead> = match location.gzip {
+        true => Box::new(BufReader::new(GzDecoder::new(BufReader::new(file)))),
+        false => Box::new(BufReader::new(file)),
+    };
+    copy(&mut (&mut reader).take(location.record_offset), &mut sink())?;
+
+    let mut position = 0;
+    let fields = match read_record_head(&mut reader, &mut position)? {
+        Some((fields, _)) => fields,
+        None => return Ok(None),
+    };
+    let (url, block_len) = match (fields.get("warc-target-uri"), content_length(&fields)) {
+        (Some(url), Some(block_len)) => match Url::parse(url) {
+            Ok(url) => (url, block_len),
+            Err(_) => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let mut block = vec![];
+    reader.take(block_len).read_to_end(&mut block)?;
+    Ok(parse_http_response(url, &block))
+}
+
+pub fn read_directory_entry(dir: &Path, url: &Url) -> IOResult<Option<(DirectoryEntry, Bytes)>> {
+    let key = directory_key(url);
+    let meta_path = dir.join(format!("{}.json", key));
+    if !meta_path.exists() {
+        return Ok(None);
+    }
+    let entry: DirectoryEntry = serde_json::from_slice(&read(&meta_path)?)?;
+    let body = read(dir.join(format!("{}.body", key)))?;
+    Ok(Some((entry, Bytes::from(body))))
+}
+
+// A directory archive serving the given pages as text/html, for tests that run a whole crawl offline
+#[cfg(test)]
+pub(crate) fn directory_fixture(pages: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crawler-replay-{}", nanoid::nanoid!(8)));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (url, body) in pages {
+        let url = Url::parse(url).unwrap();
+        let entry = DirectoryEntry {
+            url: url.clone(),
+            status: 200,
+            headers: vec![("content-type".to_string(), "text/html".to_string())],
+            stored_at: None,
+        };
+        let key = directory_key(&url);
+        std::fs::write(dir.join(format!("{}.json", key)), serde_json::to_vec(&entry).unwrap()).unwrap();
+        std::fs::write(dir.join(format!("{}.body", key)), body).unwrap();
+    }
+    dir
+}
@@ -1,6 +1,9 @@
 use bytes::Bytes;
-use encoding_rs::UTF_8;
-use reqwest::{header, Response, Result as ReqwestResult};
+use encoding_rs::{Encoding, UTF_8};
+use log::error;
+use reqwest::header::HeaderMap;
+use reqwest::{header, Response, Result as ReqwestResult, StatusCode, Version};
+use url::Url;
 use serde::{self, Deserialize};
 use std::borrow::Cow;
 
@@ -17,6 +20,14 @@ impl RespAdaptMarker {
     pub fn is_bytes(&self) -> bool {
         matches!(self, RespAdaptMarker::Bytes)
     }
+
+    // As written in the config
+    pub fn name(&self) -> &'static str {
+        match self {
+            RespAdaptMarker::Text => "Text",
+            RespAdaptMarker::Bytes => "Bytes",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -30,38 +41,72 @@ pub enum Resp {
     },
 }
 
+// A response read in full, from the network or from a replay archive
+#[derive(Debug, Clone)]
+pub struct FetchedResponse {
+    pub url: Url,
+    pub status: StatusCode,
+    pub version: Version,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl FetchedResponse {
+    pub async fn read(resp: Response) -> ReqwestResult<Self> {
+        Ok(FetchedResponse {
+            url: resp.url().clone(),
+            status: resp.status(),
+            version: resp.version(),
+            headers: resp.headers().clone(),
+            body: resp.bytes().await?,
+        })
+    }
+
+    pub fn adopt(&self, marker: &RespAdaptMarker) -> Resp {
+        Resp::from_parts(marker, &self.url, &self.headers, self.body.clone())
+    }
+}
+
 impl Resp {
-    pub async fn adopt(marker: &RespAdaptMarker, resp: Response) -> ReqwestResult<Resp> {
-        match &*marker {
-            RespAdaptMarker::Text => Ok(Resp::RespText(resp.text().await?)),
-            RespAdaptMarker::Bytes => {
-                let mime_type = resp
-                    .headers()
-                    .get(header::CONTENT_TYPE)
-                    .and_then(|value| value.to_str().ok())
-                    .and_then(|value| value.parse::<Mime>().ok());
+    pub fn from_parts(marker: &RespAdaptMarker, url: &Url, headers: &HeaderMap, bts: Bytes) -> Resp {
+        let mime_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<Mime>().ok());
 
+        match marker {
+            RespAdaptMarker::Text => {
+                // Decodes like reqwest's `text()`: charset from Content-Type, UTF-8 otherwise
+                let encoding = mime_type
+                    .as_ref()
+                    .and_then(|mm| mm.get_param("charset"))
+                    .and_then(|charset| Encoding::for_label(charset.as_str().as_bytes()))
+                    .unwrap_or(UTF_8);
+                let (text, _, _) = encoding.decode(&bts);
+                Resp::RespText(text.into_owned())
+            }
+            RespAdaptMarker::Bytes => {
                 let file_type = mime_type.as_ref().map(|mm| mm.subtype().to_string());
 
-                let mut filename = resp
-                    .url()
-                    .clone()
+                let mut filename = url
                     .path_segments()
-                    .unwrap_or_else(|| panic!("Failed to get filepath from URL: {:?}", resp.url()))
+                    .unwrap_or_else(|| panic!("Failed to get filepath from URL: {:?}", url))
                     .collect::<Vec<_>>()
                     .join("_");
 
-                if file_type.is_some() && !filename.ends_with(file_type.as_ref().unwrap()) {
-                    filename.push_str(&format!(".{}", file_type.unwrap()));
+                if let Some(file_type) = file_type {
+                    if !filename.ends_with(&file_type) {
+                        filename.push_str(&format!(".{}", file_type));
+                    }
                 }
 
                 // println!("NEW FILENAME {}", filename);
 
-                Ok(Resp::RespBytes {
-                    bts: resp.bytes().await?,
+                Resp::RespBytes {
+                    bts,
                     filename,
                     mime_type,
-                })
+                }
             }
         }
     }
@@ -95,13 +140,13 @@ impl Resp {
 
     pub fn from_bytes(&self, convert_to: &RespAdaptMarker) -> Self {
         if self.res_type_marker() != RespAdaptMarker::Bytes {
-            println!(
+            error!(
                 "Trying convert response from Bytes, but self type is {:?}",
                 self.res_type_marker()
             );
             panic!();
         } else if convert_to == &RespAdaptMarker::Bytes {
-            println!(
+            error!(
                 "Trying to convert from Bytes to {:?}, which doesn't make sense",
                 convert_to
             );
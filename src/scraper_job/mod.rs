@@ -1,13 +1,21 @@
 mod scraper_iterator;
+mod target;
 use scraper_iterator::ScraperIterator;
+use target::Target;
 
 use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Instant;
 
-use reqwest::{header, Client, Error, RequestBuilder};
+use reqwest::header::HeaderMap;
+use reqwest::{header, Client, Error, Request, RequestBuilder, StatusCode};
 
+use log::{error, Level};
+use nanoid::nanoid;
 use serde::Deserialize;
+use serde_json::Value;
 use tokio::runtime::Handle;
 use tokio::task::block_in_place;
 use url::Url;
@@ -17,11 +25,21 @@ use crate::client_config::APP_USER_AGENT;
 use crate::custom_types::PinnedFutureSender;
 use crate::errors::ProcessorError;
 use crate::headers::de_headers;
+use crate::http_cache::HttpCache;
+use crate::incremental::StateStore;
+use crate::limits::CrawlLimits;
+use crate::sink::Sinks;
+use crate::stats::CrawlStats;
+use crate::logging::{log_ctx, log_job};
 use crate::parser::{
-    FinishedProcessingResult, NextProcessingStep, ProcessingResultUnit,
+    check_steps, has_only_when, FinishedProcessingResult, NextProcessingStep, ProcessingResultUnit, ProcessingStep,
+    ResultContext, StepInput,
 };
-use crate::response_adaptor::{Resp, RespAdaptMarker};
+use crate::replay::ReplayArchive;
+use crate::response_adaptor::{FetchedResponse, Resp, RespAdaptMarker};
+use crate::warc::{ExchangeRequest, WarcCapture};
 
+use async_recursion::async_recursion;
 use futures::{stream, StreamExt};
 
 #[derive(Debug, Deserialize, Clone)]
@@ -36,6 +54,21 @@ pub enum HTTPParameterType {
     Suffix(String),
 }
 
+impl HTTPParameterType {
+    // Suffix values are named after the suffix, "/page-" -> "page"
+    pub fn param_name(&self) -> String {
+        match self {
+            HTTPParameterType::Name(name) => name.clone(),
+            HTTPParameterType::Suffix(suffix) => {
+                match suffix.trim_matches(|c: char| !c.is_alphanumeric()) {
+                    "" => "suffix".to_string(),
+                    name => name.to_string(),
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub enum DynamicParameters {
     IntRange {
@@ -110,7 +143,11 @@ pub struct PlainScraperJob {
     #[serde(default)]
     headers: header::HeaderMap,
     #[serde(default)]
-    targets: BTreeMap<RespAdaptMarker, Vec<NextProcessingStep>>,
+    targets: BTreeMap<RespAdaptMarker, Target>,
+    #[serde(default)]
+    warc: Option<WarcCapture>,
+    #[serde(default)]
+    http_cache: Option<HttpCache>,
 }
 
 impl TryFrom<PlainScraperJob> for ScraperJob {
@@ -133,17 +170,30 @@ impl TryFrom<PlainScraperJob> for ScraperJob {
                         AuthResult::ClientSession(auth_client) => client = auth_client
                     },
                     Err(e) => {
-                        eprintln!("FAILED TO AUTH {:?}", e);
+                        error!("FAILED TO AUTH {:?}", e);
                         panic!("FAIL");
                     }
                 };
-        } else {}
+        }
 
         Ok(ScraperJob {
             client,
             default_parameters: scraper_job.default_parameters,
             dynamic_parameters: scraper_job.dynamic_parameters,
             targets: scraper_job.targets,
+            warc: scraper_job.warc,
+            http_cache: scraper_job.http_cache,
+            step_path: String::new(),
+            vars: BTreeMap::new(),
+            params: BTreeMap::new(),
+            parent_urls: Vec::new(),
+            replay: None,
+            state: None,
+            job_id: String::new(),
+            depth: 0,
+            stats: Arc::default(),
+            limits: Arc::default(),
+            sinks: Sinks::default(),
         })
     }
 }
@@ -158,7 +208,36 @@ pub struct ScraperJob {
     #[serde(skip_deserializing)]
     client: Client,
     #[serde(default)]
-    targets: BTreeMap<RespAdaptMarker, Vec<NextProcessingStep>>,
+    targets: BTreeMap<RespAdaptMarker, Target>,
+    #[serde(default)]
+    warc: Option<WarcCapture>,
+    #[serde(default)]
+    http_cache: Option<HttpCache>,
+    #[serde(skip_deserializing)]
+    step_path: String,
+    #[serde(skip_deserializing)]
+    vars: BTreeMap<String, String>,
+    // Dynamic parameter values of the jobs above this one
+    #[serde(skip_deserializing)]
+    params: BTreeMap<String, String>,
+    // Pages that led to this job, the start URL first
+    #[serde(skip_deserializing)]
+    parent_urls: Vec<Url>,
+    #[serde(skip_deserializing)]
+    replay: Option<Arc<ReplayArchive>>,
+    #[serde(skip_deserializing)]
+    state: Option<Arc<StateStore>>,
+    #[serde(skip_deserializing)]
+    job_id: String,
+    // Number of Scrape steps above this job
+    #[serde(skip_deserializing)]
+    depth: usize,
+    #[serde(skip_deserializing)]
+    stats: Arc<CrawlStats>,
+    #[serde(skip_deserializing)]
+    limits: Arc<CrawlLimits>,
+    #[serde(skip_deserializing)]
+    sinks: Sinks,
 }
 
 impl ScraperJob {
@@ -175,168 +254,582 @@ impl ScraperJob {
         new_url
     }
 
-    pub fn iter(self: &ScraperJob) -> ScraperIterator {
+    // Drops the targets whose filter rejects the headers, false once the filters rejected every target
+    fn retain_accepted(&self, targets: &mut Vec<(&RespAdaptMarker, &Target)>, url: &Url, headers: &HeaderMap) -> bool {
+        let before = targets.len();
+        targets.retain(|(_, target)| match (target.filter(), target.check(headers)) {
+            (Some(filter), Err(reason)) => {
+                filter.skip(url, &reason);
+                self.stats.record_filtered();
+                false
+            }
+            _ => true,
+        });
+        before == 0 || !targets.is_empty()
+    }
+
+    // Sent only when every target asks for it, otherwise the page is downloaded anyway
+    async fn passes_preflight(&self, targets: &mut Vec<(&RespAdaptMarker, &Target)>, url: &Url) -> bool {
+        if targets.is_empty() || !targets.iter().all(|(_, target)| target.wants_preflight()) {
+            return true;
+        }
+        // Servers that reject HEAD are still checked on the GET response
+        match self.client.head(url.clone()).send().await {
+            Ok(head_resp) if head_resp.status().is_success() => {
+                self.retain_accepted(targets, url, head_resp.headers())
+            }
+            Ok(head_resp) => {
+                log_job!(Level::Warn, self, url, "HEAD pre-flight for {} returned {}, checking the GET response instead", url, head_resp.status());
+                true
+            }
+            Err(e) => {
+                log_job!(Level::Warn, self, url, "HEAD pre-flight failed for {}: {}, checking the GET response instead", url, e);
+                true
+            }
+        }
+    }
+
+    pub fn with_step_path(mut self, step_path: &str) -> Self {
+        self.step_path = step_path.to_string();
+        self
+    }
+
+    pub fn with_replay(mut self, replay: Arc<ReplayArchive>) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    pub fn with_stats(mut self, stats: Arc<CrawlStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Arc<CrawlLimits>) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_sinks(mut self, sinks: Sinks) -> Self {
+        self.sinks = sinks;
+        self
+    }
+
+    pub fn with_state(mut self, state: Arc<StateStore>) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    // `targets` is narrowed down to the ones whose filter accepts the response
+    async fn fetch(&self, request: Request, targets: &mut Vec<(&RespAdaptMarker, &Target)>) -> Option<FetchedResponse> {
+        // Replayed responses never touch the network, even when the archive misses a URL
+        if let Some(replay) = &self.replay {
+            let (replay, url) = (replay.clone(), request.url().clone());
+            let lookup = tokio::task::spawn_blocking(move || replay.lookup(&url))
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+            let fetched = match lookup {
+                Ok(Some(fetched)) => fetched,
+                Ok(None) => {
+                    log_job!(Level::Warn, self, request.url(), "Not in replay archive, skipping {}", request.url());
+                    return None;
+                }
+                Err(e) => {
+                    log_job!(Level::Error, self, request.url(), "Failed to read {} from replay archive: {}", request.url(), e);
+                    return None;
+                }
+            };
+            log_job!(Level::Info, self, request.url(), "Replayed {}", request.url());
+            self.stats.record_replayed();
+            return self.retain_accepted(targets, &fetched.url, &fetched.headers).then_some(fetched);
+        }
+
+        let mut request = request;
+        let cached = match &self.http_cache {
+            Some(cache) => cache.lookup(request.url()).await.map(|cached| (cache, cached)),
+            None => None,
+        };
+        match &cached {
+            Some((cache, cached)) => {
+                if cached.is_fresh(cache.freshness()) {
+                    log_job!(Level::Info, self, request.url(), "Fresh in HTTP cache, not requesting {}", request.url());
+                    self.stats.record_from_cache();
+                    let fetched = cached.to_response();
+                    return self.retain_accepted(targets, &fetched.url, &fetched.headers).then_some(fetched);
+                }
+                cached.add_validators(&mut request);
+            }
+            // A cached copy is checked against the filter once the server confirms it
+            None => {
+                if !self.passes_preflight(targets, request.url()).await {
+                    return None;
+                }
+            }
+        }
+
+        let exchange_request = ExchangeRequest {
+            method: request.method().clone(),
+            url: request.url().clone(),
+            headers: request.headers().clone(),
+        };
+        let in_flight = self.stats.request_started();
+        let started = Instant::now();
+        let resp = match self.client.execute(request).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                let error = ProcessorError::from(e);
+                log_job!(Level::Error, self, exchange_request.url, "{}", error);
+                self.stats.record_failed_page(&exchange_request.url, &error);
+                return None;
+            }
+        };
+        let not_modified = resp.status() == StatusCode::NOT_MODIFIED && cached.is_some();
+        // Checked before the body is read, so rejected downloads are never transferred
+        if !not_modified && !self.retain_accepted(targets, &exchange_request.url, resp.headers()) {
+            return None;
+        }
+        let fetched = match FetchedResponse::read(resp).await {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                let error = ProcessorError::ResponseAdoptionError(e);
+                log_job!(Level::Error, self, exchange_request.url, "{}", error);
+                self.stats.record_failed_page(&exchange_request.url, &error);
+                return None;
+            }
+        };
+        drop(in_flight);
+        self.stats.record_fetch(
+            &exchange_request.url,
+            fetched.status.as_u16(),
+            fetched.body.len(),
+            started.elapsed(),
+        );
+        log::info!(
+            job_id = self.job_id.as_str(),
+            depth = self.depth,
+            url = fetched.url.as_str(),
+            step_path = self.step_path.as_str(),
+            status = fetched.status.as_u16(),
+            duration_ms = started.elapsed().as_millis() as u64;
+            "Fetched {} ({}, {} bytes)", fetched.url, fetched.status, fetched.body.len()
+        );
+        if let Some(warc) = &self.warc {
+            warc.record(&exchange_request, &fetched).await;
+        }
+
+        match cached {
+            Some((cache, cached)) if not_modified => {
+                log_job!(Level::Info, self, exchange_request.url, "Not modified, serving {} from HTTP cache", exchange_request.url);
+                self.stats.record_from_cache();
+                let fetched = cache.revalidated(&exchange_request.url, cached, &fetched.headers).await;
+                self.retain_accepted(targets, &fetched.url, &fetched.headers).then_some(fetched)
+            }
+            _ => {
+                if let Some(cache) = &self.http_cache {
+                    cache.store(&exchange_request.url, &fetched).await;
+                }
+                Some(fetched)
+            }
+        }
+    }
+
+    // Checked when the config is loaded, so mismatched steps don't fail one result at a time
+    pub fn check_targets(&self, path: &str) -> Result<(), String> {
+        self.targets.iter().try_for_each(|(marker, target)| {
+            let steps_path = target.steps_path(&format!("{}/targets", path), marker);
+            check_steps(target.next_steps(), StepInput::Response, &steps_path)
+        })
+    }
+
+    pub fn has_only_when(&self) -> bool {
+        self.targets.values().any(|target| has_only_when(target.next_steps()))
+    }
+
+    pub fn iter(self: &ScraperJob) -> ScraperIterator<'_> {
         let dyn_params_iterator = self.dynamic_parameters.as_ref().map(DynParamsIterator::new);
         ScraperIterator::new(dyn_params_iterator, self)
     }
 
-    pub async fn run(self, url: Url, sender: PinnedFutureSender) {
+    pub async fn run(mut self, url: Url, sender: PinnedFutureSender) {
+        self.job_id = nanoid!(10);
+        let _running = self.stats.job_started(self.depth);
         let url_ref = &url;
         let scraper_ref = &self;
         let sender_ref = &sender;
         stream::iter(scraper_ref.iter().with_url(url_ref))
-            .for_each_concurrent(15, |resp| async move {
-                let resp = resp
-                    .send()
-                    .await
-                    .expect("FAILED TO GET RESPONSE FROM URL: {url:?}");
-                let mut targets_iter = scraper_ref.targets.iter();
-                let proc = targets_iter.next();
-
-                // Assert that only one type of next step was in pipeline
-                assert!(targets_iter.len() == 0);
-
-                if let Some((marker, steps)) = proc {
-                        let adopted_bytes_response_res = Resp::adopt(marker, resp).await;
-                        let handled_response = scraper_ref
-                            .handle_adopted_response_res(
-                                adopted_bytes_response_res,
-                                steps,
-                                sender_ref,
-                            )
-                            .await;
-                        if marker.is_bytes() {
-                            if let Some(response_copy) = &handled_response {
-                                stream::iter(targets_iter)
-                                    .for_each_concurrent(15, |(marker, steps)| async move {
-                                        let nxt_adoption_stage = response_copy.from_bytes(marker);
-                                        scraper_ref
-                                            .handle_adopted_response_res(
-                                                Ok(nxt_adoption_stage),
-                                                steps,
-                                                sender_ref,
-                                            )
-                                            .await;
-                                    })
-                                    .await;
-                                }
-                        }
-                } else {}
+            .for_each_concurrent(15, |(req, dyn_param)| async move {
+                let request = match req.build() {
+                    Ok(request) => request,
+                    Err(e) => {
+                        let error = ProcessorError::from(e);
+                        log_job!(Level::Error, scraper_ref, url_ref, "{}", error);
+                        scraper_ref.stats.record_error(url_ref, &error);
+                        return;
+                    }
+                };
+                if !scraper_ref.limits.start_page() {
+                    log_job!(Level::Debug, scraper_ref, request.url(), "Page limit reached, skipping {}", request.url());
+                    return;
+                }
+                let mut targets = scraper_ref.targets.iter().collect::<Vec<_>>();
+                let fetched = match scraper_ref.fetch(request, &mut targets).await {
+                    Some(fetched) => fetched,
+                    None => return,
+                };
+                scraper_ref.stats.record_page(&fetched.url, fetched.status.as_u16());
+                // A target skipped by its filter leaves the other targets of the page alone
+                for (marker, target) in targets {
+                    scraper_ref
+                        .handle_adopted_response_res(
+                            Ok(fetched.adopt(marker)),
+                            target.next_steps(),
+                            &scraper_ref.targets_context(&fetched.url, marker, target, dyn_param.as_ref()),
+                            sender_ref,
+                        )
+                        .await;
+                }
             })
             .await;
     }
 
+    fn targets_context(
+        &self,
+        source_url: &Url,
+        marker: &RespAdaptMarker,
+        target: &Target,
+        dyn_param: Option<&(String, String)>,
+    ) -> ResultContext {
+        // Query parameters of the nearest fetched URL win over the ones inherited from parent jobs
+        let mut vars = self.vars.clone();
+        source_url.query_pairs().for_each(|(name, value)| {
+            vars.insert(format!("param.{}", name), value.into_owned());
+        });
+        let ctx = ResultContext::new(
+            source_url.clone(),
+            target.steps_path(&format!("{}/targets", self.step_path), marker),
+        )
+        .with_vars(vars)
+        .with_params(self.params.clone());
+        // The dynamic parameter is recorded even when it is a path suffix rather than a query pair
+        let ctx = match dyn_param {
+            Some((name, value)) => ctx.with_param(name, value),
+            None => ctx,
+        };
+        ctx.with_parent_urls(self.parent_urls.clone())
+        .with_producer(&self.step_path)
+        .with_job(&self.job_id, self.depth)
+        .with_stats(self.stats.clone())
+        .with_sinks(self.sinks.clone())
+    }
+
     pub async fn handle_adopted_response_res(
         &self,
         adopted_response_res: Result<Resp, Error>,
         steps: &[NextProcessingStep],
+        ctx: &ResultContext,
         sender: &PinnedFutureSender,
     ) -> Option<Resp> {
-        if adopted_response_res.is_err() {
-            eprintln!(
-                "{}",
-                ProcessorError::from(adopted_response_res.unwrap_err())
-            );
-            None
-        } else {
-            let adopted_response = adopted_response_res.unwrap();
-            self.process_adopted_response(&adopted_response, steps, sender)
-                .await;
-            Some(adopted_response)
+        match adopted_response_res {
+            Err(e) => {
+                let error = ProcessorError::ResponseAdoptionError(e);
+                log_ctx!(Level::Error, ctx, "{}", error);
+                ctx.stats.record_error(&ctx.source_url, &error);
+                None
+            }
+            Ok(adopted_response) => {
+                self.process_adopted_response(&adopted_response, steps, ctx, sender)
+                    .await;
+                Some(adopted_response)
+            }
         }
     }
 
+    #[async_recursion]
     pub async fn process_adopted_response(
         &self,
         adopted_response: &Resp,
         steps: &[NextProcessingStep],
+        ctx: &ResultContext,
         sender: &PinnedFutureSender,
     ) {
-        for step in steps {
+        for (step_index, step) in steps.iter().enumerate() {
+            let step_ctx = ctx.child(&format!("/{}", step_index));
             match step {
                 NextProcessingStep::Store(storage) => {
-                    storage.store(adopted_response).await;
+                    storage.store(adopted_response, &step_ctx).await;
                 }
                 NextProcessingStep::Process(proc) => {
                     let step_result = proc.process(adopted_response);
                     match step_result {
                         Ok(ref results) => match results {
                             FinishedProcessingResult::VectorResult(result_vector) => {
-                                proc.next_steps().iter().for_each(|proc_step| {
-                                    result_vector.iter().for_each(|proc_result| {
-                                        self.process_processed_result(
-                                            proc_result,
-                                            proc_step,
-                                            sender,
-                                        )
-                                    });
-                                });
+                                step_ctx.stats.record_results(&step_ctx.step_path, result_vector.len());
+                                self.process_next_steps(result_vector, proc, &step_ctx, sender)
+                                    .await;
                             }
                             FinishedProcessingResult::NothingRequired => {}
                         },
                         Err(e) => {
-                            println!("ERROR TRYING TO HANDLE {:?}", e);
+                            log_ctx!(Level::Error, step_ctx, "ERROR TRYING TO HANDLE {:?}", e);
+                            step_ctx.record_step_error(&e);
                             // todo!("HANDLE PROCESSING ERROR")
                         }
                     }
                 }
+                NextProcessingStep::OnlyWhen(only_when) => {
+                    if let Some(pending) = only_when.passes_response(self.state.as_deref(), &step_ctx, adopted_response) {
+                        let steps_ctx = step_ctx.child("/OnlyWhen/next_steps").tracking_failures();
+                        self.process_adopted_response(adopted_response, only_when.next_steps(), &steps_ctx, sender)
+                            .await;
+                        pending.finish(&step_ctx, &steps_ctx);
+                    }
+                }
                 NextProcessingStep::Scrape(_) => {
-                    unreachable!("Cannot run Scrape as handler for ScraperJob result.")
+                    let error = ProcessorError::StepMismatchError(step.mismatch(StepInput::Response, &step_ctx.step_path));
+                    log_ctx!(Level::Error, step_ctx, "{}", error);
+                    step_ctx.record_step_error(&error);
                 }
             }
         }
     }
 
-    pub fn process_processed_result(
+    async fn process_next_steps(
+        &self,
+        results: &[ProcessingResultUnit],
+        proc: &ProcessingStep,
+        ctx: &ResultContext,
+        sender: &PinnedFutureSender,
+    ) {
+        let ctx = &ctx.clone().with_producer(&ctx.step_path);
+        self.sinks.accept(results, ctx).await;
+        for (step_index, proc_step) in proc.next_steps().iter().enumerate() {
+            let step_ctx = ctx.child(&format!("/Process/next_steps/{}", step_index));
+            for proc_result in results {
+                self.process_processed_result(proc_result, proc_step, &step_ctx, sender)
+                    .await;
+            }
+        }
+    }
+
+    #[async_recursion]
+    pub async fn process_processed_result(
         &self,
         proc_result: &ProcessingResultUnit,
         next_proc_step: &NextProcessingStep,
+        ctx: &ResultContext,
         sender: &PinnedFutureSender,
     ) {
+        // A form parameter counts as a parameter for the steps it is handed to
+        let form_ctx;
+        let ctx = match proc_result {
+            ProcessingResultUnit::FormParameter { name, value } => {
+                form_ctx = ctx.with_param(name, value);
+                &form_ctx
+            }
+            _ => ctx,
+        };
         match (proc_result, next_proc_step) {
             (ProcessingResultUnit::URL(url), NextProcessingStep::Scrape(scraper)) => {
-                self.spawn_new_scraper_from_url(scraper, sender, url);
+                self.spawn_new_scraper_from_url(scraper, sender, url, ctx);
             }
             (ProcessingResultUnit::Str(text), NextProcessingStep::Process(proc)) => {
-                match proc.process_string_result(text) {
-                    Ok(proc_result) => match proc_result {
-                        FinishedProcessingResult::VectorResult(results) => {
-                            results.iter().for_each(|res| {
-                                proc.next_steps().iter().for_each(|next_step| {
-                                    self.process_processed_result(res, next_step, sender);
-                                });
-                            })
-                        }
-                        FinishedProcessingResult::NothingRequired => {}
-                    },
-                    Err(_) => {
-                        todo!("LOGGING INNER");
+                self.process_string_with_step(text, proc, ctx, sender).await;
+            }
+            (ProcessingResultUnit::Record(record), NextProcessingStep::Process(proc)) => {
+                // Nested steps see the record as a JSON object
+                let record_json = Value::Object(record.clone()).to_string();
+                self.process_string_with_step(&record_json, proc, &ctx.with_record(record), sender)
+                    .await;
+            }
+            (proc_unit, NextProcessingStep::OnlyWhen(only_when)) => {
+                if let Some(pending) = only_when.passes_result(self.state.as_deref(), ctx, proc_unit) {
+                    let steps_ctx = ctx.tracking_failures();
+                    for (step_index, step) in only_when.next_steps().iter().enumerate() {
+                        let step_ctx = steps_ctx.child(&format!("/OnlyWhen/next_steps/{}", step_index));
+                        self.process_processed_result(proc_unit, step, &step_ctx, sender)
+                            .await;
                     }
+                    pending.finish(ctx, &steps_ctx);
                 }
             }
+            (proc_unit, NextProcessingStep::Store(storage)) => {
+                storage.store_result(proc_unit, ctx).await;
+            }
+            // Only reachable through Custom steps, whose results are not known until they run
             (proc_unit, next_step) => {
-                unreachable!("Cannot process {:?} & {:?}", proc_unit, next_step);
+                let error = ProcessorError::StepMismatchError(
+                    next_step.mismatch(StepInput::of_result(proc_unit), &ctx.step_path),
+                );
+                log_ctx!(Level::Error, ctx, "{}", error);
+                ctx.record_step_error(&error);
             }
         };
     }
 
+    async fn process_string_with_step(
+        &self,
+        text: &str,
+        proc: &ProcessingStep,
+        ctx: &ResultContext,
+        sender: &PinnedFutureSender,
+    ) {
+        match proc.process_string_result(text) {
+            Ok(proc_result) => match proc_result {
+                FinishedProcessingResult::VectorResult(results) => {
+                    ctx.stats.record_results(&ctx.step_path, results.len());
+                    self.process_next_steps(&results, proc, ctx, sender).await;
+                }
+                FinishedProcessingResult::NothingRequired => {}
+            },
+            Err(e) => {
+                log_ctx!(Level::Error, ctx, "Failed to process {:?}: {}", text, e);
+                ctx.record_step_error(&e);
+            }
+        }
+    }
+
     pub fn spawn_new_scraper_from_url(
         &self,
         next_scraper_job: &ScraperJob,
         sender: &PinnedFutureSender,
         url: &Url,
+        ctx: &ResultContext,
     ) {
+        if !self.limits.allows_depth(self.depth + 1) {
+            log_ctx!(Level::Debug, ctx, "Depth limit reached, not scraping {}", url);
+            return;
+        }
         let sender_clone = sender.clone();
         let url_clone = url.clone();
         let mut new_job = next_scraper_job.clone();
         new_job.client = self.client.clone();
+        new_job.step_path = format!("{}/Scrape", ctx.step_path);
+        new_job.vars = ctx.vars.clone();
+        new_job.params = ctx.params.clone();
+        new_job.parent_urls = ctx.url_chain();
+        new_job.depth = self.depth + 1;
+        // Nested jobs share the WARC files and the HTTP cache unless they configure their own
+        if new_job.warc.is_none() {
+            new_job.warc = self.warc.clone();
+        }
+        if new_job.http_cache.is_none() {
+            new_job.http_cache = self.http_cache.clone();
+        }
+        new_job.replay = self.replay.clone();
+        log_ctx!(Level::Debug, ctx, "Queued Scrape of {}", url);
+        new_job.state = self.state.clone();
+        new_job.stats = self.stats.clone();
+        new_job.limits = self.limits.clone();
+        new_job.sinks = self.sinks.clone();
 
+        // Counted as queued until the stream picks the job up, including while waiting for room in the channel
+        let stats = self.stats.clone();
+        stats.job_queued();
         tokio::spawn(async move {
-            if sender_clone
-                .send(Box::pin(new_job.run(url_clone, sender_clone.clone())))
-                .await.is_err() {
-                    eprintln!("Failed to start new Scraperjob.")
-                };
+            let job_stats = stats.clone();
+            let job_sender = sender_clone.clone();
+            let job = async move {
+                job_stats.job_dequeued();
+                new_job.run(url_clone, job_sender).await
+            };
+            if sender_clone.send(Box::pin(job)).await.is_err() {
+                stats.job_dequeued();
+                error!("Failed to start new Scraperjob.")
+            };
         });
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+
+    fn scraper(targets: Value) -> ScraperJob {
+        serde_json::from_value(json!({"targets": targets})).unwrap()
+    }
+
+    fn process(proc_result: Value, next_steps: Value) -> Value {
+        json!({"Process": {"type": "Html", "selector": "a", "capture_elements": "All",
+            "selector_target": {"Attr": "href"}, "proc_result": proc_result, "next_steps": next_steps}})
+    }
+
+    fn extract(next_steps: Value) -> Value {
+        json!({"Process": {"type": "Extract", "container": {"Html": "li"}, "fields": {"title": {}}, "next_steps": next_steps}})
+    }
+
+    fn scrape() -> Value {
+        json!({"Scrape": {"targets": {"Text": []}}})
+    }
+
+    #[test]
+    fn steps_that_cant_take_the_results_above_are_rejected() {
+        let check = |targets: Value| scraper(targets).check_targets("/scraper");
+        assert!(check(json!({"Text": [process(json!("URL"), json!([scrape()]))]})).is_ok());
+        assert!(check(json!({"Text": [extract(json!([{"OnlyWhen": {"next_steps": [process(json!("Str"), json!([]))]}}]))]})).is_ok());
+
+        let record_to_scrape = check(json!({"Text": [extract(json!([{"OnlyWhen": {"next_steps": [scrape()]}}]))]}));
+        assert_eq!(
+            record_to_scrape.unwrap_err(),
+            "Scrape step at /scraper/targets/Text/0/Process/next_steps/0/OnlyWhen/next_steps/0 can't take Record input"
+        );
+        assert!(check(json!({"Text": [process(json!("URL"), json!([process(json!("Str"), json!([]))]))]})).is_err());
+        assert!(check(json!({"Text": [process(json!({"FormParameter": "q"}), json!([process(json!("Str"), json!([]))]))]})).is_err());
+        assert!(check(json!({"Text": [scrape()]})).is_err());
+        // Nested jobs are checked too
+        let nested = json!({"Scrape": {"targets": {"Text": [extract(json!([scrape()]))]}}});
+        assert!(check(json!({"Text": [process(json!("URL"), json!([nested]))]})).is_err());
+
+        // Steps of a filtered target sit under its next_steps
+        let filtered = check(json!({"Bytes": {"filter": {"head_preflight": true}, "next_steps": [scrape()]}}));
+        assert_eq!(filtered.unwrap_err(), "Scrape step at /scraper/targets/Bytes/next_steps/0 can't take Response input");
+
+        let config = json!({"scraper": {"targets": {"Text": [extract(json!([scrape()]))]}}, "urls": []});
+        assert!(crate::Crawler::from_value(config).is_err());
+    }
+
+    #[tokio::test]
+    async fn a_mismatched_result_fails_the_step_not_the_crawl() {
+        let job = scraper(json!({"Text": []}));
+        let step: NextProcessingStep = serde_json::from_value(scrape()).unwrap();
+        let record = ProcessingResultUnit::Record(serde_json::from_str(r#"{"title": "A"}"#).unwrap());
+        let ctx = ResultContext::new(Url::parse("https://example.com/").unwrap(), "/scraper/0".to_string())
+            .tracking_failures();
+        let (sender, mut queued) = channel(1);
+
+        job.process_processed_result(&record, &step, &ctx, &sender).await;
+        assert!(ctx.has_failed());
+        assert_eq!(ctx.stats.snapshot().errors.get("StepMismatchError"), Some(&1));
+        drop(sender);
+        assert!(queued.recv().await.is_none());
+    }
+
+    struct RecordsFrom;
+
+    impl crate::processor::Processor for RecordsFrom {
+        fn process_text(&self, text: &str) -> crate::parser::ProcessingResult {
+            let record = serde_json::from_value(json!({"text": text})).unwrap();
+            Ok(FinishedProcessingResult::VectorResult(vec![ProcessingResultUnit::Record(record)]))
+        }
+    }
+
+    #[tokio::test]
+    async fn a_custom_record_sent_to_a_scrape_step_does_not_stop_the_crawl() {
+        crate::processor::register_processor("records_for_scrape_test", |_| Ok(Box::new(RecordsFrom))).unwrap();
+        let archive = crate::replay::directory_fixture(&[
+            ("https://example.com/a", "first"),
+            ("https://example.com/b", "second"),
+        ]);
+        let config = json!({
+            "scraper": {"targets": {"Text": [{"Process": {"type": "Custom", "name": "records_for_scrape_test",
+                "next_steps": [scrape()]}}]}},
+            "urls": ["https://example.com/a", "https://example.com/b"],
+            "replay": {"Directory": archive},
+        });
+        let summary = crate::Crawler::from_value(config).unwrap().run().await.unwrap();
+        std::fs::remove_dir_all(&archive).unwrap();
+        assert_eq!(summary.stats.pages_done, 2);
+        assert_eq!(summary.stats.errors.get("StepMismatchError"), Some(&2));
+    }
+}
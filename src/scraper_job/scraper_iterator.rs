@@ -1,5 +1,6 @@
 use std::ops::RangeInclusive;
 
+use log::debug;
 use reqwest::{RequestBuilder};
 use url::Url;
 
@@ -13,7 +14,7 @@ pub struct ScraperIterator<'a> {
 }
 
 impl<'a> ScraperIterator<'a> {
-    pub fn new(dyn_params: Option<DynParamsIterator>, scraper: &ScraperJob) -> ScraperIterator {
+    pub fn new(dyn_params: Option<DynParamsIterator>, scraper: &'a ScraperJob) -> ScraperIterator<'a> {
         let end_bound = match &dyn_params {
             Some(params) => params.len(),
             None => 1,
@@ -33,8 +34,9 @@ impl<'a> ScraperIterator<'a> {
     }
 }
 
+// A request together with the name and value of the dynamic parameter it was built with
 impl<'a> Iterator for ScraperIterator<'a> {
-    type Item = RequestBuilder;
+    type Item = (RequestBuilder, Option<(String, String)>);
 
     fn next(&mut self) -> Option<Self::Item> {
         self.counter.next().and_then(|_| {
@@ -43,31 +45,28 @@ impl<'a> Iterator for ScraperIterator<'a> {
             let scraper = &self.scraper;
             url.as_ref().map(|just_url| {
                 let mut url_with_defaults = scraper.new_url_with_defaults(just_url);
-                match params {
-                    Some(dyn_params_iterator) => {
-                        // let self_ref = &self;
-
-                        let param_type = dyn_params_iterator.name.clone();
-                        let param_value = dyn_params_iterator
-                            .next()
-                            .expect("Unexpected value of iterator");
-                        match param_type {
-                            HTTPParameterType::Name(param_name) => {
-                                url_with_defaults
-                                    .query_pairs_mut()
-                                    .append_pair(&param_name, &param_value);
-                            }
-                            HTTPParameterType::Suffix(suff) => {
-                                let new_path =
-                                    &format!("{}{}{}", url_with_defaults.path(), suff, param_value);
-                                url_with_defaults.set_path(new_path);
-                                println!("NEW URL {}", url_with_defaults);
-                            }
-                        };
-                    }
-                    None => (),
-                }
-                scraper.new_request_with_url(url_with_defaults)
+                let dyn_param = params.as_mut().map(|dyn_params_iterator| {
+                    let param_type = dyn_params_iterator.name.clone();
+                    let param_value = dyn_params_iterator
+                        .next()
+                        .expect("Unexpected value of iterator");
+                    let param_name = param_type.param_name();
+                    match param_type {
+                        HTTPParameterType::Name(param_name) => {
+                            url_with_defaults
+                                .query_pairs_mut()
+                                .append_pair(&param_name, &param_value);
+                        }
+                        HTTPParameterType::Suffix(suff) => {
+                            let new_path =
+                                &format!("{}{}{}", url_with_defaults.path(), suff, param_value);
+                            url_with_defaults.set_path(new_path);
+                            debug!("NEW URL {}", url_with_defaults);
+                        }
+                    };
+                    (param_name, param_value)
+                });
+                (scraper.new_request_with_url(url_with_defaults), dyn_param)
             })
         })
     }
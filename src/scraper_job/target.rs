@@ -0,0 +1,103 @@
+use reqwest::header::HeaderMap;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::filters::{DownloadFilter, SkipReason};
+use crate::parser::{pointer_token, NextProcessingStep};
+use crate::response_adaptor::RespAdaptMarker;
+
+#[derive(Deserialize)]
+struct FilteredTarget {
+    #[serde(default)]
+    filter: Option<DownloadFilter>,
+    #[serde(default)]
+    next_steps: Vec<NextProcessingStep>,
+}
+
+// Either a plain list of steps or `{"filter":{...}, "next_steps":[...]}`
+#[derive(Debug, Clone, Default)]
+pub struct Target {
+    filter: Option<DownloadFilter>,
+    next_steps: Vec<NextProcessingStep>,
+    // Written as an object, so the steps sit under its "next_steps"
+    object_form: bool,
+}
+
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let target = match Value::deserialize(deserializer)? {
+            steps @ Value::Array(_) => Target {
+                filter: None,
+                next_steps: serde_json::from_value(steps).map_err(de::Error::custom)?,
+                object_form: false,
+            },
+            filtered @ Value::Object(_) => {
+                let filtered: FilteredTarget =
+                    serde_json::from_value(filtered).map_err(de::Error::custom)?;
+                Target {
+                    filter: filtered.filter,
+                    next_steps: filtered.next_steps,
+                    object_form: true,
+                }
+            }
+            other => {
+                return Err(de::Error::custom(format!(
+                    "Target must be a list of steps or an object with next_steps, got {}",
+                    other
+                )))
+            }
+        };
+        Ok(target)
+    }
+}
+
+impl Target {
+    pub fn next_steps(&self) -> &[NextProcessingStep] {
+        &self.next_steps
+    }
+
+    // JSON pointer of the steps list, given the pointer of the "targets" object holding the target
+    pub fn steps_path(&self, targets_path: &str, marker: &RespAdaptMarker) -> String {
+        let target_path = format!("{}/{}", targets_path, pointer_token(marker.name()));
+        match self.object_form {
+            true => format!("{}/next_steps", target_path),
+            false => target_path,
+        }
+    }
+
+    pub fn filter(&self) -> Option<&DownloadFilter> {
+        self.filter.as_ref()
+    }
+
+    pub fn wants_preflight(&self) -> bool {
+        self.filter.as_ref().is_some_and(DownloadFilter::head_preflight)
+    }
+
+    pub fn check(&self, headers: &HeaderMap) -> Result<(), SkipReason> {
+        match &self.filter {
+            Some(filter) => filter.check(headers),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn steps_path_follows_the_target_form() {
+        let listed: Target = serde_json::from_value(json!([])).unwrap();
+        assert_eq!(listed.steps_path("/scraper/targets", &RespAdaptMarker::Text), "/scraper/targets/Text");
+
+        let filtered: Target = serde_json::from_value(json!({"filter": {}, "next_steps": []})).unwrap();
+        assert_eq!(
+            filtered.steps_path("/scraper/targets", &RespAdaptMarker::Bytes),
+            "/scraper/targets/Bytes/next_steps"
+        );
+    }
+}
@@ -1,27 +1,149 @@
 use std::fmt::Debug;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 
-use serde::Deserialize;
+use log::{info, warn};
+use serde::{de, Deserialize, Deserializer};
 
 use url::Url;
 use futures::{stream, StreamExt};
 
 use crate::custom_types::PinnedFutureSender;
+use crate::errors::CrawlError;
 
+use crate::incremental::StateStore;
+use crate::limits::CrawlLimits;
+use crate::logging::LogSettings;
+use crate::metrics::MetricsSettings;
+use crate::progress::ProgressSettings;
+use crate::replay::{ReplayArchive, ReplaySource};
 use crate::scraper_job::ScraperJob;
+use crate::sink::Sinks;
+use crate::stats::CrawlStats;
 
 
+fn de_checked_scraper<'de, D>(deserializer: D) -> Result<ScraperJob, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let scraper = ScraperJob::deserialize(deserializer)?;
+    scraper.check_targets("/scraper").map_err(de::Error::custom)?;
+    Ok(scraper)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ScraperUnit {
+    #[serde(deserialize_with = "de_checked_scraper")]
     scraper: ScraperJob,
     urls: Vec<Url>,
+    #[serde(default)]
+    replay: Option<ReplaySource>,
+    // What every OnlyWhen step saw last time, one file per workflow
+    #[serde(default)]
+    state: Option<PathBuf>,
+    #[serde(default)]
+    log: LogSettings,
+    // JSON file the end-of-run statistics are written to
+    #[serde(default)]
+    report: Option<PathBuf>,
+    // Local Prometheus endpoint, only served while the crawl runs
+    #[serde(default)]
+    metrics: Option<MetricsSettings>,
+    #[serde(default)]
+    progress: Option<ProgressSettings>,
+    #[serde(default)]
+    limits: CrawlLimits,
+    #[serde(skip)]
+    stats: Arc<CrawlStats>,
+    #[serde(skip)]
+    sinks: Sinks,
+    #[serde(skip)]
+    replay_archive: Option<Arc<ReplayArchive>>,
+    #[serde(skip)]
+    state_store: Option<Arc<StateStore>>,
 }
 
 impl ScraperUnit {
+    pub fn log_settings(&self) -> &LogSettings {
+        &self.log
+    }
+
+    pub fn report_path(&self) -> Option<&PathBuf> {
+        self.report.as_ref()
+    }
+
+    pub fn metrics_settings(&self) -> Option<&MetricsSettings> {
+        self.metrics.as_ref()
+    }
+
+    pub fn progress_settings(&self) -> Option<&ProgressSettings> {
+        self.progress.as_ref()
+    }
+
+    pub fn limits(&self) -> &CrawlLimits {
+        &self.limits
+    }
+
+    pub fn limits_mut(&mut self) -> &mut CrawlLimits {
+        &mut self.limits
+    }
+
+    pub fn sinks_mut(&mut self) -> &mut Sinks {
+        &mut self.sinks
+    }
+
+    // Shared by every job of the run, so it can be read once all of them finished
+    pub fn stats(&self) -> Arc<CrawlStats> {
+        self.stats.clone()
+    }
+
+    pub fn set_replay(&mut self, source: ReplaySource) {
+        self.replay = Some(source);
+    }
+
+    // Opens the replay archive and the state store, so a crawl that can't read them doesn't start
+    pub async fn open(mut self) -> Result<Self, CrawlError> {
+        if let Some(source) = &self.replay {
+            let opened = {
+                let source = source.clone();
+                tokio::task::spawn_blocking(move || ReplayArchive::open(&source)).await
+            };
+            let archive = opened
+                .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+                .map_err(|e| CrawlError::ReplayArchive(source.clone(), e))?;
+            match archive.indexed_urls() {
+                Some(count) => info!("Replaying {} URLs from {:?}", count, source),
+                None => info!("Replaying from {:?}", source),
+            }
+            self.replay_archive = Some(Arc::new(archive));
+        }
+        if let Some(path) = &self.state {
+            let state = StateStore::open(path).map_err(|e| CrawlError::StateStore(path.clone(), e))?;
+            info!("Loaded state of {} items from {:?}", state.tracked_items(), path);
+            self.state_store = Some(Arc::new(state));
+        } else if self.scraper.has_only_when() {
+            warn!("No state store configured, OnlyWhen steps let everything through");
+        }
+        Ok(self)
+    }
+
     pub async fn run(self, sender: PinnedFutureSender) {
         let ref_sender = &sender;
-        let scraper = &(self.scraper);
-        stream::iter(self.urls.into_iter())
+        let mut scraper = self
+            .scraper
+            .with_step_path("/scraper")
+            .with_stats(self.stats.clone())
+            .with_limits(Arc::new(self.limits))
+            .with_sinks(self.sinks);
+        if let Some(archive) = self.replay_archive {
+            scraper = scraper.with_replay(archive);
+        }
+        if let Some(state) = self.state_store {
+            scraper = scraper.with_state(state);
+        }
+        let scraper = &scraper;
+        stream::iter(self.urls)
             .for_each_concurrent(2, |url| async move {
                 scraper.clone().run(url.clone(), ref_sender.clone()).await
             })
@@ -0,0 +1,125 @@
+use scraper::{Html, Selector};
+use serde::Deserialize;
+
+use crate::parser::de_selector;
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum ScriptLocator {
+    Selector(#[serde(deserialize_with = "de_selector")] Selector),
+    Id(String),
+    Variable(String),
+}
+
+// Returns the balanced JSON object or array starting at `start`, string literals are respected
+fn json_literal_at(text: &str, start: usize) -> Option<&str> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (offset, ch) in text[start..].char_indices() {
+        if in_string {
+            match ch {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match ch {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(&text[start..start + offset + ch.len_utf8()]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn first_json_literal(text: &str) -> Option<&str> {
+    let trimmed = text.trim();
+    if serde_json::from_str::<serde_json::Value>(trimmed).is_ok() {
+        return Some(trimmed);
+    }
+    text.find(['{', '['])
+        .and_then(|start| json_literal_at(text, start))
+}
+
+fn is_identifier_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch == '$'
+}
+
+fn assigned_json_literal<'a>(text: &'a str, variable: &str) -> Option<&'a str> {
+    text.match_indices(variable).find_map(|(pos, _)| {
+        // `var` must not match the end of `myvar`; `window.var` still matches
+        if text[..pos].chars().next_back().is_some_and(is_identifier_char) {
+            return None;
+        }
+        let rest = &text[pos + variable.len()..];
+        let after_eq = rest.trim_start().strip_prefix('=')?;
+        if after_eq.starts_with('=') {
+            return None;
+        }
+        let value = after_eq.trim_start();
+        if !value.starts_with('{') && !value.starts_with('[') {
+            return None;
+        }
+        json_literal_at(text, text.len() - value.len())
+    })
+}
+
+pub fn find_script_json(html: &str, locator: &ScriptLocator) -> Vec<String> {
+    let document_tree = Html::parse_document(html);
+    let script_texts = |selector: &Selector| {
+        document_tree
+            .select(selector)
+            .map(|script| script.text().collect::<String>())
+            .collect::<Vec<String>>()
+    };
+    let scripts = Selector::parse("script").expect("Static selector is valid");
+
+    match locator {
+        ScriptLocator::Selector(selector) => script_texts(selector)
+            .iter()
+            .filter_map(|text| first_json_literal(text).map(str::to_string))
+            .collect(),
+        // Compared as is, so quotes or brackets in the id can't break out of a selector
+        ScriptLocator::Id(id) => document_tree
+            .select(&scripts)
+            .filter(|script| script.value().id() == Some(id.as_str()))
+            .filter_map(|script| first_json_literal(&script.text().collect::<String>()).map(str::to_string))
+            .collect(),
+        ScriptLocator::Variable(variable) => script_texts(&scripts)
+            .iter()
+            .filter_map(|text| assigned_json_literal(text, variable).map(str::to_string))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assignments_are_found_by_whole_variable_name() {
+        let script = r#"var myvar = {"wrong": 1}; var = 2; window.var = {"a": [1, {"b": "}"}]};"#;
+        assert_eq!(assigned_json_literal(script, "var"), Some(r#"{"a": [1, {"b": "}"}]}"#));
+        assert_eq!(assigned_json_literal(script, "myvar"), Some(r#"{"wrong": 1}"#));
+        assert_eq!(assigned_json_literal("if (state == {}) {}", "state"), None);
+        assert_eq!(assigned_json_literal("items=[1,2]", "items"), Some("[1,2]"));
+    }
+
+    #[test]
+    fn ids_are_matched_without_going_through_a_selector() {
+        let html = r#"<script id='a"]'>{"quoted": true}</script><script id="b">[1]</script>"#;
+        let id = |id: &str| find_script_json(html, &ScriptLocator::Id(id.to_string()));
+        assert_eq!(id("a\"]"), vec![r#"{"quoted": true}"#]);
+        assert_eq!(id("b"), vec!["[1]"]);
+        assert!(id("c").is_empty());
+    }
+}
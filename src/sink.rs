@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
+use log::debug;
+use tokio::sync::mpsc::Sender;
+
+use crate::parser::{ProcessingResultUnit, Provenance, ResultContext};
+
+// A result of a Process step as handed to sinks
+#[derive(Debug, Clone)]
+pub struct CrawlResult {
+    pub result: ProcessingResultUnit,
+    pub provenance: Provenance,
+}
+
+// Receives every result the crawl extracts. The crawl waits for `accept`,
+// so a slow sink throttles it instead of piling results up in memory
+pub trait ResultSink: Send + Sync {
+    fn accept(&self, result: CrawlResult) -> BoxFuture<'_, ()>;
+}
+
+impl ResultSink for Sender<CrawlResult> {
+    fn accept(&self, result: CrawlResult) -> BoxFuture<'_, ()> {
+        async move {
+            // The receiver may stop listening before the crawl ends
+            if self.send(result).await.is_err() {
+                debug!("Result receiver is gone, dropping result");
+            }
+        }
+        .boxed()
+    }
+}
+
+pub struct CallbackSink<F>(F);
+
+impl<F, Fut> CallbackSink<F>
+where
+    F: Fn(CrawlResult) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    pub fn new(callback: F) -> Self {
+        CallbackSink(callback)
+    }
+}
+
+impl<F, Fut> ResultSink for CallbackSink<F>
+where
+    F: Fn(CrawlResult) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn accept(&self, result: CrawlResult) -> BoxFuture<'_, ()> {
+        (self.0)(result).boxed()
+    }
+}
+
+type NamedSinks = BTreeMap<String, Arc<dyn ResultSink>>;
+
+// Cheap to clone, every job and result context holds one
+#[derive(Clone, Default)]
+pub struct Sinks {
+    // Receive every result of every Process step
+    all: Arc<Vec<Arc<dyn ResultSink>>>,
+    // Receive what Channel and Callback storages push to them, by name
+    channels: Arc<NamedSinks>,
+    callbacks: Arc<NamedSinks>,
+}
+
+impl Debug for Sinks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Sinks({} for all results, channels {:?}, callbacks {:?})",
+            self.all.len(),
+            self.channels.keys().collect::<Vec<&String>>(),
+            self.callbacks.keys().collect::<Vec<&String>>()
+        )
+    }
+}
+
+impl Sinks {
+    pub fn push(&mut self, sink: Arc<dyn ResultSink>) {
+        Arc::make_mut(&mut self.all).push(sink);
+    }
+
+    pub fn add_channel(&mut self, name: &str, sink: Arc<dyn ResultSink>) {
+        Arc::make_mut(&mut self.channels).insert(name.to_string(), sink);
+    }
+
+    pub fn add_callback(&mut self, name: &str, sink: Arc<dyn ResultSink>) {
+        Arc::make_mut(&mut self.callbacks).insert(name.to_string(), sink);
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&Arc<dyn ResultSink>> {
+        self.channels.get(name)
+    }
+
+    pub fn callback(&self, name: &str) -> Option<&Arc<dyn ResultSink>> {
+        self.callbacks.get(name)
+    }
+
+    pub async fn accept(&self, results: &[ProcessingResultUnit], ctx: &ResultContext) {
+        if self.all.is_empty() {
+            return;
+        }
+        let provenance = ctx.provenance();
+        for result in results {
+            let provenance = match result {
+                ProcessingResultUnit::FormParameter { name, value } => ctx.with_param(name, value).provenance(),
+                _ => provenance.clone(),
+            };
+            for sink in self.all.iter() {
+                sink.accept(CrawlResult {
+                    result: result.clone(),
+                    provenance: provenance.clone(),
+                })
+                .await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use serde_json::json;
+    use tokio::sync::mpsc::channel;
+    use url::Url;
+
+    use super::*;
+
+    fn ctx() -> ResultContext {
+        ResultContext::new(Url::parse("https://example.com/").unwrap(), "/scraper/targets/Text/0".to_string())
+    }
+
+    fn strs(count: usize) -> Vec<ProcessingResultUnit> {
+        (0..count).map(|i| ProcessingResultUnit::Str(i.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn a_full_channel_holds_the_results_back() {
+        let (sender, mut receiver) = channel(1);
+        let mut sinks = Sinks::default();
+        sinks.push(Arc::new(sender));
+        let accepting = tokio::spawn(async move { sinks.accept(&strs(3), &ctx()).await });
+
+        // One result fits in the channel, the second waits for room
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!accepting.is_finished());
+        for expected in ["0", "1", "2"] {
+            match receiver.recv().await.unwrap().result {
+                ProcessingResultUnit::Str(text) => assert_eq!(text, expected),
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+        accepting.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_dropped_receiver_drops_the_results() {
+        let (sender, receiver) = channel(1);
+        drop(receiver);
+        let mut sinks = Sinks::default();
+        sinks.push(Arc::new(sender));
+        tokio::time::timeout(Duration::from_secs(1), sinks.accept(&strs(3), &ctx()))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_crawl_waits_for_the_results_stream_and_ends_without_it() {
+        let archive = crate::replay::directory_fixture(&[
+            ("https://example.com/", "<ul><li>a</li><li>b</li><li>c</li></ul>"),
+        ]);
+        let config = json!({
+            "scraper": {"targets": {"Text": [{"Process": {"type": "Html", "selector": "li",
+                "capture_elements": "All", "selector_target": "Text", "proc_result": "Str", "next_steps": []}}]}},
+            "urls": ["https://example.com/"],
+            "replay": {"Directory": archive},
+        });
+        let crawler = || crate::Crawler::from_value(config.clone()).unwrap();
+
+        let (mut results, crawl) = crawler().results(1);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!crawl.is_finished());
+        assert!(results.next().await.is_some());
+        drop(results);
+        let summary = tokio::time::timeout(Duration::from_secs(5), crawl).await.unwrap().unwrap().unwrap();
+        assert_eq!(summary.stats.pages_done, 1);
+
+        let (results, crawl) = crawler().results(1);
+        drop(results);
+        let summary = tokio::time::timeout(Duration::from_secs(5), crawl).await.unwrap().unwrap().unwrap();
+        std::fs::remove_dir_all(&archive).unwrap();
+        assert_eq!(summary.stats.steps.values().map(|step| step.results).sum::<u64>(), 3);
+    }
+}
@@ -0,0 +1,460 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{create_dir_all, write};
+use std::io::Result as IOResult;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::info;
+use serde::Serialize;
+use url::Url;
+
+use crate::errors::ProcessorError;
+
+// Upper bounds of the latency buckets; slower requests land in the last, unbounded bucket
+pub const LATENCY_BUCKETS_MS: [u64; 10] = [10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+const RECENT_ERRORS: usize = 5;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyHistogram {
+    pub bounds_ms: Vec<u64>,
+    // One more than `bounds_ms`, the last one counting requests above every bound
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub sum_ms: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            bounds_ms: LATENCY_BUCKETS_MS.to_vec(),
+            counts: vec![0; LATENCY_BUCKETS_MS.len() + 1],
+            count: 0,
+            sum_ms: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    fn observe(&mut self, latency_ms: u64) {
+        let bucket = self
+            .bounds_ms
+            .iter()
+            .position(|bound| latency_ms <= *bound)
+            .unwrap_or(self.bounds_ms.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ms += latency_ms;
+    }
+
+    // Upper bound of the bucket holding the given quantile, None when it is above every bound
+    fn quantile_bound(&self, quantile: f64) -> Option<u64> {
+        let rank = (self.count as f64 * quantile).ceil() as u64;
+        let mut seen = 0;
+        for (bound, count) in self.bounds_ms.iter().zip(&self.counts) {
+            seen += count;
+            if seen >= rank {
+                return Some(*bound);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StepStats {
+    pub results: u64,
+    // Runs that captured nothing
+    pub empty: u64,
+    pub errors: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageStats {
+    pub written: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub bytes: u64,
+}
+
+pub enum StoreOutcome {
+    // Bytes of the file, upload or row written
+    Written(usize),
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatsSnapshot {
+    // Gauges, only meaningful while the crawl runs
+    #[serde(skip)]
+    pub in_flight_requests: u64,
+    #[serde(skip)]
+    pub queued_jobs: u64,
+    #[serde(skip)]
+    pub running_jobs: u64,
+    // Running jobs by depth
+    #[serde(skip)]
+    pub running_depths: BTreeMap<usize, u64>,
+    // Newest last
+    #[serde(skip)]
+    pub recent_errors: VecDeque<String>,
+    pub max_depth: usize,
+    // Fetched, cached or replayed with a status below 400
+    pub pages_done: u64,
+    // Failed to fetch or answered with a status of 400 and above
+    pub pages_failed: u64,
+    pub requests: u64,
+    pub requests_per_host: BTreeMap<String, u64>,
+    pub status_codes: BTreeMap<u16, u64>,
+    pub bytes_downloaded: u64,
+    pub latency_ms: LatencyHistogram,
+    pub from_cache: u64,
+    pub replayed: u64,
+    pub filtered: u64,
+    // By step path
+    pub steps: BTreeMap<String, StepStats>,
+    // By storage kind
+    pub storage: BTreeMap<String, StorageStats>,
+    // By ProcessorError variant
+    pub errors: BTreeMap<String, u64>,
+}
+
+// Counters of one ScraperUnit run, shared by every job it spawns
+#[derive(Debug)]
+pub struct CrawlStats {
+    started: Instant,
+    data: Mutex<StatsSnapshot>,
+}
+
+impl Default for CrawlStats {
+    fn default() -> Self {
+        CrawlStats {
+            started: Instant::now(),
+            data: Mutex::new(StatsSnapshot::default()),
+        }
+    }
+}
+
+enum Gauge {
+    InFlightRequests,
+    RunningJobs(usize),
+}
+
+// Decrements its gauge when dropped, so early returns are counted too
+pub struct GaugeGuard<'a> {
+    stats: &'a CrawlStats,
+    gauge: Gauge,
+}
+
+impl Drop for GaugeGuard<'_> {
+    fn drop(&mut self) {
+        let mut data = self.stats.data();
+        let value = match self.gauge {
+            Gauge::InFlightRequests => &mut data.in_flight_requests,
+            Gauge::RunningJobs(depth) => {
+                if let Some(running) = data.running_depths.get_mut(&depth) {
+                    *running -= 1;
+                    if *running == 0 {
+                        data.running_depths.remove(&depth);
+                    }
+                }
+                &mut data.running_jobs
+            }
+        };
+        *value = value.saturating_sub(1);
+    }
+}
+
+pub(crate) fn human_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+fn push_recent(recent: &mut VecDeque<String>, entry: String) {
+    if recent.len() == RECENT_ERRORS {
+        recent.pop_front();
+    }
+    recent.push_back(entry);
+}
+
+fn join_counts<K: std::fmt::Display>(counts: &BTreeMap<K, u64>) -> String {
+    counts
+        .iter()
+        .map(|(key, count)| format!("{} {}", key, count))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl CrawlStats {
+    fn data(&self) -> MutexGuard<'_, StatsSnapshot> {
+        self.data.lock().expect("Stats lock is poisoned")
+    }
+
+    pub fn request_started(&self) -> GaugeGuard<'_> {
+        self.data().in_flight_requests += 1;
+        GaugeGuard {
+            stats: self,
+            gauge: Gauge::InFlightRequests,
+        }
+    }
+
+    // A job waits in the queue from being sent to the channel until it starts running
+    pub fn job_queued(&self) {
+        self.data().queued_jobs += 1;
+    }
+
+    pub fn job_dequeued(&self) {
+        let mut data = self.data();
+        data.queued_jobs = data.queued_jobs.saturating_sub(1);
+    }
+
+    pub fn job_started(&self, depth: usize) -> GaugeGuard<'_> {
+        let mut data = self.data();
+        data.running_jobs += 1;
+        *data.running_depths.entry(depth).or_default() += 1;
+        data.max_depth = data.max_depth.max(depth);
+        drop(data);
+        GaugeGuard {
+            stats: self,
+            gauge: Gauge::RunningJobs(depth),
+        }
+    }
+
+    pub fn record_fetch(&self, url: &Url, status: u16, bytes: usize, latency: Duration) {
+        let mut data = self.data();
+        data.requests += 1;
+        *data
+            .requests_per_host
+            .entry(url.host_str().unwrap_or_default().to_string())
+            .or_default() += 1;
+        *data.status_codes.entry(status).or_default() += 1;
+        data.bytes_downloaded += bytes as u64;
+        data.latency_ms.observe(latency.as_millis() as u64);
+    }
+
+    pub fn record_page(&self, url: &Url, status: u16) {
+        let mut data = self.data();
+        if status < 400 {
+            data.pages_done += 1;
+        } else {
+            data.pages_failed += 1;
+            push_recent(&mut data.recent_errors, format!("{}: HTTP {}", url, status));
+        }
+    }
+
+    pub fn record_failed_page(&self, url: &Url, error: &ProcessorError) {
+        self.record_error(url, error);
+        self.data().pages_failed += 1;
+    }
+
+    pub fn record_from_cache(&self) {
+        self.data().from_cache += 1;
+    }
+
+    pub fn record_replayed(&self) {
+        self.data().replayed += 1;
+    }
+
+    pub fn record_filtered(&self) {
+        self.data().filtered += 1;
+    }
+
+    pub fn record_results(&self, step_path: &str, results: usize) {
+        self.data()
+            .steps
+            .entry(step_path.to_string())
+            .or_default()
+            .results += results as u64;
+    }
+
+    pub fn record_error(&self, url: &Url, error: &ProcessorError) {
+        let mut data = self.data();
+        *data.errors.entry(error.kind().to_string()).or_default() += 1;
+        if !matches!(error, ProcessorError::NothingToCaptureError) {
+            push_recent(&mut data.recent_errors, format!("{}: {}", url, error));
+        }
+    }
+
+    // A step that captured nothing counts as empty rather than failed
+    pub fn record_step_error(&self, url: &Url, step_path: &str, error: &ProcessorError) {
+        self.record_error(url, error);
+        let mut data = self.data();
+        let step = data.steps.entry(step_path.to_string()).or_default();
+        match error {
+            ProcessorError::NothingToCaptureError => step.empty += 1,
+            _ => step.errors += 1,
+        }
+    }
+
+    pub fn record_store(&self, storage_kind: &str, outcome: StoreOutcome) {
+        let mut data = self.data();
+        let storage = data.storage.entry(storage_kind.to_string()).or_default();
+        match outcome {
+            StoreOutcome::Written(bytes) => {
+                storage.written += 1;
+                storage.bytes += bytes as u64;
+            }
+            StoreOutcome::Skipped => storage.skipped += 1,
+            StoreOutcome::Failed => storage.failed += 1,
+        }
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        self.data().clone()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn log_summary(&self) {
+        let data = self.snapshot();
+        info!(
+            "Crawl finished in {:.1} s: {} pages done, {} failed, max depth {}, {} requests, {} downloaded, {} from cache, {} replayed, {} filtered",
+            self.elapsed().as_secs_f64(),
+            data.pages_done,
+            data.pages_failed,
+            data.max_depth,
+            data.requests,
+            human_bytes(data.bytes_downloaded),
+            data.from_cache,
+            data.replayed,
+            data.filtered
+        );
+        if data.requests > 0 {
+            let bound = |quantile| match data.latency_ms.quantile_bound(quantile) {
+                Some(bound) => format!("<= {} ms", bound),
+                None => format!("> {} ms", LATENCY_BUCKETS_MS[LATENCY_BUCKETS_MS.len() - 1]),
+            };
+            info!(
+                "Latency: mean {} ms, p50 {}, p95 {}",
+                data.latency_ms.sum_ms / data.latency_ms.count,
+                bound(0.5),
+                bound(0.95)
+            );
+            info!("Status codes: {}", join_counts(&data.status_codes));
+            info!("Requests per host: {}", join_counts(&data.requests_per_host));
+        }
+        for (step_path, step) in &data.steps {
+            info!(
+                "Step {}: {} results, {} empty, {} errors",
+                step_path, step.results, step.empty, step.errors
+            );
+        }
+        for (kind, storage) in &data.storage {
+            info!(
+                "Storage {}: {} written ({}), {} skipped, {} failed",
+                kind,
+                storage.written,
+                human_bytes(storage.bytes),
+                storage.skipped,
+                storage.failed
+            );
+        }
+        if !data.errors.is_empty() {
+            info!("Errors: {}", join_counts(&data.errors));
+        }
+    }
+
+    pub fn write_report(&self, path: &Path) -> IOResult<()> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                create_dir_all(parent)?;
+            }
+        }
+        let mut report = serde_json::to_value(self.snapshot())?;
+        report["finished_at"] = serde_json::json!(Utc::now().to_rfc3339());
+        report["elapsed_secs"] = serde_json::json!(self.elapsed().as_secs_f64());
+        write(path, serde_json::to_vec_pretty(&report)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_depths_follow_the_jobs() {
+        let stats = CrawlStats::default();
+        let start = stats.job_started(0);
+        let nested = stats.job_started(2);
+        let sibling = stats.job_started(2);
+        assert_eq!(stats.snapshot().running_depths.keys().next_back(), Some(&2));
+
+        drop(nested);
+        assert_eq!(stats.snapshot().running_depths.keys().next_back(), Some(&2));
+        drop(sibling);
+        let data = stats.snapshot();
+        assert_eq!(data.running_depths.keys().next_back(), Some(&0));
+        assert_eq!(data.max_depth, 2);
+        drop(start);
+        assert!(stats.snapshot().running_depths.is_empty());
+    }
+
+    #[test]
+    fn latencies_land_in_the_first_bucket_they_fit() {
+        let mut histogram = LatencyHistogram::default();
+        for latency_ms in [0, 10, 11, 10000, 10001] {
+            histogram.observe(latency_ms);
+        }
+        assert_eq!(histogram.counts, vec![2, 1, 0, 0, 0, 0, 0, 0, 0, 1, 1]);
+        assert_eq!(histogram.count, 5);
+        assert_eq!(histogram.sum_ms, 20022);
+
+        assert_eq!(histogram.quantile_bound(0.4), Some(10));
+        assert_eq!(histogram.quantile_bound(0.6), Some(25));
+        assert_eq!(histogram.quantile_bound(0.8), Some(10000));
+        assert_eq!(histogram.quantile_bound(1.0), None);
+    }
+
+    #[test]
+    fn gauges_drop_back_when_their_guard_is_dropped() {
+        let stats = CrawlStats::default();
+        let request = stats.request_started();
+        let other_request = stats.request_started();
+        let job = stats.job_started(1);
+        let data = stats.snapshot();
+        assert_eq!((data.in_flight_requests, data.running_jobs), (2, 1));
+
+        drop(request);
+        assert_eq!(stats.snapshot().in_flight_requests, 1);
+        drop(other_request);
+        drop(job);
+        let data = stats.snapshot();
+        assert_eq!((data.in_flight_requests, data.running_jobs), (0, 0));
+        assert!(data.running_depths.is_empty());
+    }
+
+    #[test]
+    fn report_holds_the_counters_but_not_the_gauges() {
+        let stats = CrawlStats::default();
+        let _request = stats.request_started();
+        let url = Url::parse("https://example.com/page").unwrap();
+        stats.record_fetch(&url, 200, 1024, Duration::from_millis(30));
+        stats.record_fetch(&url, 404, 10, Duration::from_millis(5));
+
+        let dir = std::env::temp_dir().join(format!("crawler-stats-{}", nanoid::nanoid!(8)));
+        let path = dir.join("reports/stats.json");
+        stats.write_report(&path).unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(report["requests"], 2);
+        assert_eq!(report["requests_per_host"]["example.com"], 2);
+        assert_eq!(report["status_codes"]["200"], 1);
+        assert_eq!(report["status_codes"]["404"], 1);
+        assert_eq!(report["bytes_downloaded"], 1034);
+        assert_eq!(report["latency_ms"]["counts"][0], 1);
+        assert_eq!(report["latency_ms"]["counts"][2], 1);
+        assert_eq!(report["latency_ms"]["sum_ms"], 35);
+        assert!(report["finished_at"].is_string());
+        assert!(report["elapsed_secs"].is_number());
+        assert!(report.get("in_flight_requests").is_none());
+    }
+}
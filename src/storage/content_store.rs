@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Result as IOResult};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use log::warn;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use super::csv_writer::CsvWriter;
+use super::json_lines::JsonLinesWriter;
+
+pub type SharedContentIndex = Arc<Mutex<Option<ContentIndex>>>;
+
+const MANIFEST_COLUMNS: [&str; 6] = ["source_url", "path", "sha256", "bytes", "mime_type", "timestamp"];
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ContentAddressing {
+    #[serde(default)]
+    index: Option<PathBuf>,
+    #[serde(default)]
+    manifest: Option<PathBuf>,
+    #[serde(skip)]
+    state: SharedContentIndex,
+}
+
+impl ContentAddressing {
+    pub fn index_path(&self, dirname: &Path) -> PathBuf {
+        self.index.clone().unwrap_or_else(|| dirname.join("index.jsonl"))
+    }
+
+    pub fn manifest_path(&self, dirname: &Path) -> PathBuf {
+        self.manifest.clone().unwrap_or_else(|| dirname.join("manifest.csv"))
+    }
+
+    pub fn state(&self) -> &SharedContentIndex {
+        &self.state
+    }
+}
+
+// 2cf24dba... -> objects/2c/2cf24dba....txt, so no directory grows too large
+pub fn object_path(sha256: &str, ext: &str) -> String {
+    format!("objects/{}/{}.{}", &sha256[..2], sha256, ext)
+}
+
+pub enum IndexUpdate {
+    New,
+    Unchanged,
+    Changed(String),
+}
+
+pub struct StoredObject {
+    pub source_url: String,
+    pub path: String,
+    pub sha256: String,
+    pub bytes: usize,
+    pub mime_type: Option<String>,
+}
+
+// URL -> content hash, loaded from the index file and kept in memory for the run
+#[derive(Debug)]
+pub struct ContentIndex {
+    hashes: HashMap<String, String>,
+    index_writer: JsonLinesWriter,
+    manifest_writer: CsvWriter,
+}
+
+fn load_hashes(index_path: &Path) -> IOResult<HashMap<String, String>> {
+    let mut hashes = HashMap::new();
+    if !index_path.exists() {
+        return Ok(hashes);
+    }
+    for line in BufReader::new(File::open(index_path)?).lines() {
+        let entry: Value = match serde_json::from_str(&line?) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Skip broken line in {:?}: {}", index_path, e);
+                continue;
+            }
+        };
+        // Later lines win, so a URL whose content changed maps to its newest hash
+        if let (Some(url), Some(sha256)) = (entry["url"].as_str(), entry["sha256"].as_str()) {
+            hashes.insert(url.to_string(), sha256.to_string());
+        }
+    }
+    Ok(hashes)
+}
+
+impl ContentIndex {
+    pub fn open(index_path: &Path, manifest_path: &Path) -> IOResult<Self> {
+        let manifest_columns = MANIFEST_COLUMNS
+            .iter()
+            .map(|column| column.to_string())
+            .collect::<Vec<String>>();
+        Ok(ContentIndex {
+            hashes: load_hashes(index_path)?,
+            index_writer: JsonLinesWriter::open(index_path, None)?,
+            manifest_writer: CsvWriter::open(manifest_path, &manifest_columns)?,
+        })
+    }
+
+    pub fn record(&mut self, object: &StoredObject) -> IOResult<IndexUpdate> {
+        let update = match self.hashes.get(&object.source_url) {
+            Some(sha256) if *sha256 == object.sha256 => return Ok(IndexUpdate::Unchanged),
+            Some(previous) => IndexUpdate::Changed(previous.clone()),
+            None => IndexUpdate::New,
+        };
+
+        self.index_writer
+            .write_value(&json!({"url": object.source_url, "sha256": object.sha256}))?;
+        let mut row = Map::new();
+        row.insert("source_url".to_string(), json!(object.source_url));
+        row.insert("path".to_string(), json!(object.path));
+        row.insert("sha256".to_string(), json!(object.sha256));
+        row.insert("bytes".to_string(), json!(object.bytes));
+        row.insert("mime_type".to_string(), json!(object.mime_type));
+        row.insert("timestamp".to_string(), json!(Utc::now().to_rfc3339()));
+        self.manifest_writer.write_row(&row)?;
+        // Later runs take the index as the truth about what changed, so it isn't left in the buffer
+        self.index_writer.flush()?;
+        self.manifest_writer.flush()?;
+
+        self.hashes.insert(object.source_url.clone(), object.sha256.clone());
+        Ok(update)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+
+    use url::Url;
+
+    use super::*;
+    use crate::parser::ResultContext;
+    use crate::response_adaptor::Resp;
+    use crate::storage::filename_template::sha256_hex;
+    use crate::storage::Storage;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("crawler-objects-{}", nanoid::nanoid!(8)))
+    }
+
+    fn object(source_url: &str, sha256: &str) -> StoredObject {
+        StoredObject {
+            source_url: source_url.to_string(),
+            path: object_path(sha256, "txt"),
+            sha256: sha256.to_string(),
+            bytes: 5,
+            mime_type: Some("text/plain".to_string()),
+        }
+    }
+
+    fn content_addressed(dir: &Path) -> Storage {
+        serde_json::from_value(json!({"LocalDrive":{
+            "dirname":{"path": dir.to_str().unwrap(), "or_create": true},
+            "content_addressed":{}
+        }}))
+        .unwrap()
+    }
+
+    async fn store(storage: &Storage, url: &str, body: &'static [u8]) -> ResultContext {
+        let ctx = ResultContext::new(Url::parse(url).unwrap(), "/scraper".to_string());
+        let resp = Resp::RespBytes {
+            bts: body.into(),
+            filename: "page.txt".to_string(),
+            mime_type: Some(mime::TEXT_PLAIN),
+        };
+        storage.store(&resp, &ctx).await;
+        ctx
+    }
+
+    #[test]
+    fn same_content_is_recorded_per_url_and_changes_are_reported() {
+        let dir = temp_dir();
+        let mut index = ContentIndex::open(&dir.join("index.jsonl"), &dir.join("manifest.csv")).unwrap();
+        assert!(matches!(index.record(&object("https://a.com/1", "aa11")).unwrap(), IndexUpdate::New));
+        assert!(matches!(index.record(&object("https://a.com/1", "aa11")).unwrap(), IndexUpdate::Unchanged));
+        // A dedupe hit: the same object under another URL
+        assert!(matches!(index.record(&object("https://a.com/2", "aa11")).unwrap(), IndexUpdate::New));
+        match index.record(&object("https://a.com/1", "bb22")).unwrap() {
+            IndexUpdate::Changed(previous) => assert_eq!(previous, "aa11"),
+            _ => panic!("Expected a change"),
+        }
+
+        assert_eq!(read_to_string(dir.join("index.jsonl")).unwrap().lines().count(), 3);
+        assert_eq!(read_to_string(dir.join("manifest.csv")).unwrap().lines().count(), 4);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_reopened_index_keeps_the_known_hashes() {
+        let dir = temp_dir();
+        let (index_path, manifest_path) = (dir.join("index.jsonl"), dir.join("manifest.csv"));
+        let mut index = ContentIndex::open(&index_path, &manifest_path).unwrap();
+        index.record(&object("https://a.com/1", "aa11")).unwrap();
+        index.record(&object("https://a.com/1", "bb22")).unwrap();
+        drop(index);
+
+        let mut reopened = ContentIndex::open(&index_path, &manifest_path).unwrap();
+        assert!(matches!(reopened.record(&object("https://a.com/1", "bb22")).unwrap(), IndexUpdate::Unchanged));
+        assert!(matches!(reopened.record(&object("https://a.com/1", "cc33")).unwrap(), IndexUpdate::Changed(_)));
+        drop(reopened);
+
+        let manifest = read_to_string(&manifest_path).unwrap();
+        assert_eq!(manifest.matches("source_url,path").count(), 1);
+        assert_eq!(manifest.lines().count(), 4);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn duplicates_are_written_once_and_indexed_per_url() {
+        let dir = temp_dir();
+        let storage = content_addressed(&dir);
+        let first = store(&storage, "https://a.com/1", b"hello").await;
+        let second = store(&storage, "https://a.com/2", b"hello").await;
+
+        let sha256 = sha256_hex(b"hello");
+        assert_eq!(read_to_string(dir.join(object_path(&sha256, "txt"))).unwrap(), "hello");
+        assert_eq!(read_to_string(dir.join("index.jsonl")).unwrap().lines().count(), 2);
+        assert_eq!(first.stats.snapshot().storage["LocalDrive"].written, 1);
+        assert_eq!(second.stats.snapshot().storage["LocalDrive"].skipped, 1);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_failed_write_is_not_indexed() {
+        let dir = temp_dir();
+        let storage = content_addressed(&dir);
+        // A file where the objects directory should be
+        write(dir.join("objects"), b"").unwrap();
+        let ctx = store(&storage, "https://a.com/1", b"hello").await;
+
+        assert!(!dir.join("index.jsonl").exists());
+        assert_eq!(ctx.stats.snapshot().storage["LocalDrive"].failed, 1);
+        assert!(ctx.has_failed());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_failed_index_update_fails_the_store() {
+        let dir = temp_dir();
+        let storage = content_addressed(&dir);
+        // A directory where the index file should be
+        create_dir_all(dir.join("index.jsonl")).unwrap();
+        let ctx = store(&storage, "https://a.com/1", b"hello").await;
+
+        let stats = ctx.stats.snapshot().storage["LocalDrive"].clone();
+        assert_eq!((stats.written, stats.failed), (0, 1));
+        assert!(ctx.has_failed());
+        remove_dir_all(&dir).unwrap();
+    }
+}
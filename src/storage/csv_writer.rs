@@ -0,0 +1,167 @@
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use csv::Writer;
+use log::error;
+use serde_json::{Map, Value};
+
+use super::cell_value;
+
+pub type SharedCsvWriter = Arc<Mutex<Option<CsvWriter>>>;
+
+// Rows are buffered, and reach the file at most this long after they were written
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct CsvWriter {
+    path: PathBuf,
+    columns: Vec<String>,
+    writer: Writer<File>,
+    flushed_at: Instant,
+}
+
+impl CsvWriter {
+    pub fn open(path: &Path, columns: &[String]) -> IOResult<Self> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                create_dir_all(parent)?;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_new_file = file.metadata()?.len() == 0;
+
+        let mut writer = Writer::from_writer(file);
+        // Appending to an existing file must not repeat the header
+        if is_new_file {
+            writer.write_record(columns)?;
+            writer.flush()?;
+        }
+        Ok(CsvWriter {
+            path: path.to_owned(),
+            columns: columns.to_vec(),
+            writer,
+            flushed_at: Instant::now(),
+        })
+    }
+
+    // Returns the bytes of cell text written
+    pub fn write_row(&mut self, row: &Map<String, Value>) -> IOResult<usize> {
+        let cells = self
+            .columns
+            .iter()
+            .map(|column| cell_value(row.get(column)))
+            .collect::<Vec<String>>();
+        self.writer.write_record(&cells)?;
+        if self.flushed_at.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(cells.iter().map(String::len).sum())
+    }
+
+    pub fn flush(&mut self) -> IOResult<()> {
+        self.writer.flush()?;
+        self.flushed_at = Instant::now();
+        Ok(())
+    }
+}
+
+// The writer is dropped with its storage at the end of the crawl, which writes out the rest
+impl Drop for CsvWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Failed to flush CSV rows to {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, remove_dir_all};
+
+    use serde_json::json;
+    use url::Url;
+
+    use super::super::result_row;
+    use super::*;
+    use crate::parser::ResultContext;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("crawler-csv-{}", nanoid::nanoid!(8)))
+    }
+
+    fn columns(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn row(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn cells_follow_the_column_order() {
+        let dir = temp_dir();
+        let path = dir.join("results.csv");
+        let mut writer = CsvWriter::open(&path, &columns(&["b", "a", "missing"])).unwrap();
+        writer.write_row(&row(json!({"a": 1, "b": ["x", "y"], "extra": true}))).unwrap();
+        drop(writer);
+
+        assert_eq!(read_to_string(&path).unwrap(), "b,a,missing\nx; y,1,\n");
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn special_characters_are_quoted() {
+        let dir = temp_dir();
+        let path = dir.join("results.csv");
+        let mut writer = CsvWriter::open(&path, &columns(&["title"])).unwrap();
+        writer.write_row(&row(json!({"title": "a, b"}))).unwrap();
+        writer.write_row(&row(json!({"title": "say \"hi\""}))).unwrap();
+        writer.write_row(&row(json!({"title": "two\nlines"}))).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(
+            read_to_string(&path).unwrap(),
+            "title\n\"a, b\"\n\"say \"\"hi\"\"\"\n\"two\nlines\"\n"
+        );
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn meta_columns_come_from_the_result_context() {
+        let dir = temp_dir();
+        let path = dir.join("results.csv");
+        let ctx = ResultContext::new(Url::parse("https://example.com/page").unwrap(), "/scraper/0".to_string());
+        let meta_columns = columns(&["value", "source_url", "step_path", "timestamp", "provenance"]);
+        let mut writer = CsvWriter::open(&path, &meta_columns).unwrap();
+        writer.write_row(&result_row(json!("found"), &ctx)).unwrap();
+        drop(writer);
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let records = reader.records().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(&records[0][0], "found");
+        assert_eq!(&records[0][1], "https://example.com/page");
+        assert_eq!(&records[0][2], "/scraper/0");
+        assert!(chrono::DateTime::parse_from_rfc3339(&records[0][3]).is_ok());
+        let provenance: Value = serde_json::from_str(&records[0][4]).unwrap();
+        assert_eq!(provenance["source_url"], "https://example.com/page");
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn appending_keeps_a_single_header() {
+        let dir = temp_dir();
+        let path = dir.join("results.csv");
+        let header = columns(&["n"]);
+        for n in 1..=2 {
+            let mut writer = CsvWriter::open(&path, &header).unwrap();
+            writer.write_row(&row(json!({ "n": n }))).unwrap();
+        }
+
+        assert_eq!(read_to_string(&path).unwrap(), "n\n1\n2\n");
+        remove_dir_all(&dir).unwrap();
+    }
+}
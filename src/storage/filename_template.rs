@@ -0,0 +1,319 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use chrono::Utc;
+use mime::Mime;
+use nanoid::nanoid;
+use percent_encoding::percent_decode_str;
+use serde::{de, Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+
+use crate::parser::ResultContext;
+
+#[derive(Debug, Clone)]
+enum Variable {
+    Host,
+    Path,
+    Basename,
+    Ext,
+    ExtFromMime,
+    Filename,
+    Sha256,
+    Date,
+    Index,
+    Nanoid,
+    Param(String),
+    Capture(String),
+}
+
+impl FromStr for Variable {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let variable = match name {
+            "host" => Variable::Host,
+            "path" => Variable::Path,
+            "basename" => Variable::Basename,
+            "ext" => Variable::Ext,
+            "ext_from_mime" => Variable::ExtFromMime,
+            "filename" => Variable::Filename,
+            "sha256" => Variable::Sha256,
+            "date" => Variable::Date,
+            "index" => Variable::Index,
+            "nanoid" => Variable::Nanoid,
+            _ => match name.split_once('.') {
+                Some(("param", param)) if !param.is_empty() => Variable::Param(param.to_string()),
+                Some(("capture", field)) if !field.is_empty() => {
+                    Variable::Capture(field.to_string())
+                }
+                _ => return Err(format!("Unknown template variable {{{}}}", name)),
+            },
+        };
+        Ok(variable)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Var(Variable),
+}
+
+// A relative file path with `{variables}`; `/` in the template or in `{path}` creates subdirectories
+#[derive(Debug, Clone)]
+pub struct FilenameTemplate {
+    source: String,
+    parts: Vec<Part>,
+    // Shared by every clone of the storage, so `{index}` keeps counting across tasks
+    counter: Arc<AtomicUsize>,
+}
+
+// Everything a template can be filled from
+pub struct TemplateInput<'a> {
+    pub filename: &'a str,
+    pub mime_type: Option<&'a Mime>,
+    pub bytes: &'a Bytes,
+    pub ctx: &'a ResultContext,
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    if !closed {
+                        return Err(format!("Unclosed variable in template {:?}", source));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Var(name.trim().parse()?));
+                }
+                '}' => return Err(format!("Unmatched '}}' in template {:?}", source)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(FilenameTemplate {
+            source: source.to_string(),
+            parts,
+            counter: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+}
+
+pub fn de_template<'de, D>(deserializer: D) -> Result<FilenameTemplate, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw_template: String = Deserialize::deserialize(deserializer)?;
+    raw_template.parse().map_err(de::Error::custom)
+}
+
+pub fn de_optional_template<'de, D>(deserializer: D) -> Result<Option<FilenameTemplate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    de_template(deserializer).map(Some)
+}
+
+// Characters that are not allowed in file names on common filesystems
+fn sanitize_segment(segment: &str) -> String {
+    segment
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn decode_segment(segment: &str) -> String {
+    percent_decode_str(segment).decode_utf8_lossy().into_owned()
+}
+
+pub fn ext_from_mime(mime_type: &Mime) -> String {
+    let subtype = mime_type.subtype().as_str();
+    let ext = match (mime_type.type_().as_str(), subtype) {
+        (_, "jpeg") => "jpg",
+        ("text", "plain") => "txt",
+        (_, "javascript") => "js",
+        (_, "x-icon") | (_, "vnd.microsoft.icon") => "ico",
+        ("audio", "mpeg") => "mp3",
+        ("video", "quicktime") => "mov",
+        (_, "octet-stream") => "bin",
+        // e.g. image/svg+xml -> svg
+        _ => subtype.split('+').next().unwrap_or(subtype),
+    };
+    ext.to_string()
+}
+
+impl FilenameTemplate {
+    fn value(&self, variable: &Variable, input: &TemplateInput) -> Result<String, String> {
+        let url = &input.ctx.source_url;
+        let last_segment = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(decode_segment)
+            .unwrap_or_default();
+        let value = match variable {
+            Variable::Host => sanitize_segment(url.host_str().unwrap_or_default()),
+            // The only variable allowed to add directories
+            Variable::Path => url
+                .path_segments()
+                .map(|segments| {
+                    segments
+                        .map(|segment| sanitize_segment(&decode_segment(segment)))
+                        .collect::<Vec<_>>()
+                        .join("/")
+                })
+                .unwrap_or_default(),
+            Variable::Basename => sanitize_segment(
+                Path::new(&last_segment)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or_default(),
+            ),
+            Variable::Ext => sanitize_segment(
+                Path::new(&last_segment)
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .unwrap_or_default(),
+            ),
+            Variable::ExtFromMime => input
+                .mime_type
+                .map(ext_from_mime)
+                .unwrap_or_else(|| "bin".to_string()),
+            Variable::Filename => sanitize_segment(input.filename),
+            Variable::Sha256 => sha256_hex(input.bytes),
+            Variable::Date => Utc::now().format("%Y-%m-%d").to_string(),
+            Variable::Index => (self.counter.fetch_add(1, Ordering::SeqCst) + 1).to_string(),
+            Variable::Nanoid => nanoid!(10),
+            Variable::Param(name) => input
+                .ctx
+                .vars
+                .get(&format!("param.{}", name))
+                .map(|value| sanitize_segment(value))
+                .ok_or_else(|| format!("no URL parameter {:?} up the chain", name))?,
+            Variable::Capture(field) => input
+                .ctx
+                .vars
+                .get(&format!("capture.{}", field))
+                .map(|value| sanitize_segment(value))
+                .ok_or_else(|| format!("no captured field {:?} up the chain", field))?,
+        };
+        Ok(value)
+    }
+
+    pub fn render(&self, input: &TemplateInput) -> Result<String, String> {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => rendered.push_str(text),
+                Part::Var(variable) => rendered.push_str(&self.value(variable, input)?),
+            }
+        }
+
+        // Never let a template escape the storage root
+        let relative_path = rendered
+            .split('/')
+            .map(sanitize_segment)
+            .filter(|segment| !segment.is_empty() && segment != "." && segment != "..")
+            .collect::<Vec<String>>()
+            .join("/");
+        if relative_path.is_empty() {
+            return Err(format!("Template {:?} rendered an empty path", self.source));
+        }
+        Ok(relative_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::*;
+
+    fn render(template: &str, url: &str) -> Result<String, String> {
+        let ctx = ResultContext::new(Url::parse(url).unwrap(), "/scraper".to_string())
+            .with_param("page", "2")
+            .with_record(&serde_json::from_str(r#"{"title": "A/B: \"C\""}"#).unwrap());
+        let input = TemplateInput {
+            filename: "photo.jpg",
+            mime_type: Some(&mime::IMAGE_JPEG),
+            bytes: &Bytes::from_static(b"data"),
+            ctx: &ctx,
+        };
+        template.parse::<FilenameTemplate>()?.render(&input)
+    }
+
+    #[test]
+    fn templates_are_checked_when_parsed() {
+        assert!("{host}/{{literal}}".parse::<FilenameTemplate>().is_ok());
+        assert!("{host".parse::<FilenameTemplate>().is_err());
+        assert!("host}".parse::<FilenameTemplate>().is_err());
+        assert!("{nope}".parse::<FilenameTemplate>().is_err());
+        assert!("{param.}".parse::<FilenameTemplate>().is_err());
+    }
+
+    #[test]
+    fn variables_are_filled_from_the_url_and_the_chain() {
+        let url = "https://example.com/img/cat%20one.png?x=1";
+        assert_eq!(render("{host}/{path}", url).unwrap(), "example.com/img/cat one.png");
+        assert_eq!(render("{basename}.{ext_from_mime}", url).unwrap(), "cat one.jpg");
+        assert_eq!(render("{{{param.page}}}-{filename}", url).unwrap(), "{2}-photo.jpg");
+        assert_eq!(render("{capture.title}", url).unwrap(), "A_B_ _C_");
+        assert!(render("{param.missing}", url).is_err());
+    }
+
+    #[test]
+    fn rendered_paths_stay_below_the_root() {
+        let url = "https://example.com/a/..%2F..%2Fetc/passwd";
+        assert_eq!(render("../{path}", url).unwrap(), "a/.._.._etc/passwd");
+        assert_eq!(render("/./x//y/", url).unwrap(), "x/y");
+        assert!(render("../..", url).is_err());
+    }
+
+    #[test]
+    fn segments_lose_characters_filesystems_reject() {
+        assert_eq!(sanitize_segment(" a/b\\c:d*e?f\"g<h>i|j\n "), "a_b_c_d_e_f_g_h_i_j_");
+        assert_eq!(sanitize_segment("plain name.txt"), "plain name.txt");
+    }
+}
@@ -0,0 +1,818 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::{Debug, Display};
+use std::future::Future;
+use std::io::Cursor;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use google_drive3::common::NoToken;
+use google_drive3::hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use google_drive3::hyper_util::client::legacy::connect::HttpConnector;
+use google_drive3::hyper_util::client::legacy::Client;
+use google_drive3::hyper_util::rt::TokioExecutor;
+use google_drive3::{api, DriveHub, Error};
+use log::warn;
+use md5::{Digest, Md5};
+use mime::Mime;
+use serde::Deserialize;
+use tokio::sync::{Mutex, OnceCell, OwnedMutexGuard};
+use tokio::time::sleep;
+
+use yup_oauth2 as oauth;
+
+// Smaller files go in a single multipart request
+const RESUMABLE_UPLOAD_THRESHOLD: usize = 5 * 1024 * 1024;
+
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+pub type DriveConnector = HttpsConnector<HttpConnector>;
+
+// The hub is built on the first upload and reused by every later one.
+// A failed build is not kept, so the next upload tries again.
+#[derive(Clone, Default)]
+pub struct SharedDriveHub(Arc<OnceCell<DriveHub<DriveConnector>>>);
+
+impl Debug for SharedDriveHub {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SharedDriveHub(initialized: {})", self.0.initialized())
+    }
+}
+
+impl SharedDriveHub {
+    pub async fn get(&self, settings: &DriveSettings) -> Result<&DriveHub<DriveConnector>, String> {
+        self.0.get_or_try_init(|| settings.build_hub()).await
+    }
+}
+
+// One lock per key, so only work on the same folder or file name waits
+#[derive(Debug, Clone, Default)]
+pub struct KeyedLocks(Arc<StdMutex<HashMap<String, Arc<Mutex<()>>>>>);
+
+impl KeyedLocks {
+    pub async fn lock(&self, key: &str) -> OwnedMutexGuard<()> {
+        let key_lock = self
+            .0
+            .lock()
+            .expect("GD lock map is poisoned")
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        key_lock.lock_owned().await
+    }
+}
+
+// Subfolder path (relative to `folder_id`) -> Drive folder id
+#[derive(Debug, Clone, Default)]
+pub struct SharedFolderCache {
+    ids: Arc<StdMutex<HashMap<String, String>>>,
+    creating: KeyedLocks,
+}
+
+impl SharedFolderCache {
+    fn get(&self, path: &str) -> Option<String> {
+        self.ids.lock().expect("GD folder cache is poisoned").get(path).cloned()
+    }
+
+    fn insert(&self, path: &str, folder_id: &str) {
+        self.ids
+            .lock()
+            .expect("GD folder cache is poisoned")
+            .insert(path.to_string(), folder_id.to_string());
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum DuplicatePolicy {
+    Skip,
+    Overwrite,
+    Version,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub enum DuplicateMatch {
+    #[default]
+    Name,
+    Md5,
+}
+
+#[derive(Debug)]
+pub enum UploadOutcome {
+    Created(String),
+    Overwritten(String),
+    Skipped(String),
+}
+
+impl Display for UploadOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadOutcome::Created(name) => write!(f, "Uploaded {:?} to GD", name),
+            UploadOutcome::Overwritten(name) => write!(f, "Overwrote {:?} in GD", name),
+            UploadOutcome::Skipped(reason) => write!(f, "Skip GD upload: {}", reason),
+        }
+    }
+}
+
+enum Existing {
+    SameContent(String),
+    SameName(String),
+}
+
+enum UploadTarget<'a> {
+    New { name: &'a str, folder_id: &'a str },
+    Replace { file_id: &'a str },
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum DriveAuthMode {
+    ServiceAccount,
+    InstalledFlow,
+    NoAuth,
+}
+
+impl FromStr for DriveAuthMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "ServiceAccount" => Ok(DriveAuthMode::ServiceAccount),
+            "InstalledFlow" => Ok(DriveAuthMode::InstalledFlow),
+            "NoAuth" => Ok(DriveAuthMode::NoAuth),
+            unknown => Err(format!("Unknown Google Drive auth mode {:?}", unknown)),
+        }
+    }
+}
+
+// Every setting falls back to its environment variable
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct DriveSettings {
+    #[serde(default)]
+    auth_mode: Option<DriveAuthMode>,
+    #[serde(default)]
+    key_path: Option<PathBuf>,
+    #[serde(default)]
+    token_cache: Option<PathBuf>,
+    #[serde(default)]
+    endpoint: Option<String>,
+}
+
+fn from_env<T: FromStr + Clone>(configured: &Option<T>, env_name: &str) -> Option<T> {
+    configured
+        .clone()
+        .or_else(|| env::var(env_name).ok().and_then(|value| value.parse().ok()))
+}
+
+impl DriveSettings {
+    fn auth_mode(&self) -> DriveAuthMode {
+        from_env(&self.auth_mode, "GOOGLE_DRIVE_AUTH_MODE").unwrap_or(DriveAuthMode::ServiceAccount)
+    }
+
+    fn key_path(&self) -> Result<PathBuf, String> {
+        from_env(&self.key_path, "GOOGLE_DRIVE_KEY_PATH").ok_or_else(|| {
+            "Google Drive needs a key: set credentials.key_path or GOOGLE_DRIVE_KEY_PATH".to_string()
+        })
+    }
+
+    // Without a token cache, tokens are kept in memory for the run
+    fn token_cache(&self) -> Option<PathBuf> {
+        from_env(&self.token_cache, "GOOGLE_DRIVE_TOKEN_CACHE")
+    }
+
+    pub fn check(&self) -> Result<(), String> {
+        if let DriveAuthMode::NoAuth = self.auth_mode() {
+            return Ok(());
+        }
+        let key_path = self.key_path()?;
+        match key_path.is_file() {
+            true => Ok(()),
+            false => Err(format!("Google Drive key {:?} does not exist", key_path)),
+        }
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        from_env(&self.endpoint, "GOOGLE_DRIVE_ENDPOINT").map(|endpoint| {
+            if endpoint.ends_with('/') {
+                endpoint
+            } else {
+                endpoint + "/"
+            }
+        })
+    }
+
+    pub async fn build_hub(&self) -> Result<DriveHub<DriveConnector>, String> {
+        // rustls can't pick a crypto provider on its own once both ring and aws-lc-rs are linked
+        let _ = rustls::crypto::ring::default_provider().install_default();
+
+        let connector = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .map_err(|e| format!("Failed to load native TLS roots: {}", e))?
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let client = Client::builder(TokioExecutor::new()).build(connector);
+
+        let mut hub = match self.auth_mode() {
+            DriveAuthMode::ServiceAccount => {
+                let key_path = self.key_path()?;
+                let key = oauth::read_service_account_key(&key_path)
+                    .await
+                    .map_err(|e| format!("Failed to read service account key {:?}: {}", key_path, e))?;
+                let mut builder = oauth::ServiceAccountAuthenticator::builder(key);
+                if let Some(token_cache) = self.token_cache() {
+                    builder = builder.persist_tokens_to_disk(token_cache);
+                }
+                let auth = builder
+                    .build()
+                    .await
+                    .map_err(|e| format!("Failed to create authenticator: {}", e))?;
+                DriveHub::new(client, auth)
+            }
+            DriveAuthMode::InstalledFlow => {
+                let key_path = self.key_path()?;
+                let secret = oauth::read_application_secret(&key_path)
+                    .await
+                    .map_err(|e| format!("Failed to read application secret {:?}: {}", key_path, e))?;
+                let mut builder = oauth::InstalledFlowAuthenticator::builder(
+                    secret,
+                    oauth::InstalledFlowReturnMethod::HTTPRedirect,
+                );
+                if let Some(token_cache) = self.token_cache() {
+                    builder = builder.persist_tokens_to_disk(token_cache);
+                }
+                let auth = builder
+                    .build()
+                    .await
+                    .map_err(|e| format!("Failed to create authenticator: {}", e))?;
+                DriveHub::new(client, auth)
+            }
+            DriveAuthMode::NoAuth => DriveHub::new(client, NoToken),
+        };
+
+        // Points the hub at a mock server, e.g. in tests
+        if let Some(endpoint) = self.endpoint() {
+            hub.base_url(format!("{}drive/v3/", endpoint));
+            hub.root_url(endpoint);
+        }
+        Ok(hub)
+    }
+}
+
+fn is_retryable(error: &Error) -> bool {
+    match error {
+        Error::HttpError(_) | Error::Io(_) => true,
+        Error::Failure(response) => {
+            response.status().is_server_error() || response.status().as_u16() == 429
+        }
+        Error::Cancelled
+        | Error::MissingToken(_)
+        | Error::UploadSizeLimitExceeded(_, _)
+        | Error::BadRequest(_)
+        | Error::MissingAPIKey
+        | Error::FieldClash(_)
+        | Error::JsonDecodeError(_, _) => false,
+    }
+}
+
+async fn with_retries<T, F, Fut>(retries: u8, action: &str, mut call: F) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match call().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < retries && is_retryable(&e) => {
+                attempt += 1;
+                warn!("{} failed ({}), retry {}/{}", action, e, attempt, retries);
+                sleep(Duration::from_millis(500 * 2u64.pow(attempt as u32))).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn md5_hex(bytes: &[u8]) -> String {
+    Md5::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+async fn list_files(
+    hub: &DriveHub<DriveConnector>,
+    query: &str,
+    retries: u8,
+) -> Result<Vec<api::File>, Error> {
+    let mut files = vec![];
+    let mut page_token: Option<String> = None;
+    loop {
+        let (_, file_list) = with_retries(retries, "Listing GD files", || {
+            let mut call = hub
+                .files()
+                .list()
+                .q(query)
+                .page_size(1000)
+                .param("fields", "nextPageToken, files(id, name, md5Checksum)");
+            if let Some(token) = &page_token {
+                call = call.page_token(token);
+            }
+            call.doit()
+        })
+        .await?;
+        files.extend(file_list.files.unwrap_or_default());
+        match file_list.next_page_token {
+            Some(token) => page_token = Some(token),
+            None => return Ok(files),
+        }
+    }
+}
+
+async fn create_folder(
+    hub: &DriveHub<DriveConnector>,
+    name: &str,
+    parent_id: &str,
+    retries: u8,
+) -> Result<String, String> {
+    let (_, folder) = with_retries(retries, "Creating GD folder", || {
+        let folder = api::File {
+            name: Some(name.to_string()),
+            mime_type: Some(FOLDER_MIME_TYPE.to_string()),
+            parents: Some(vec![parent_id.to_string()]),
+            ..Default::default()
+        };
+        // Folders have no content, the API still expects an (empty) media upload
+        hub.files()
+            .create(folder)
+            .param("fields", "id")
+            .upload(Cursor::new(vec![]), FOLDER_MIME_TYPE.parse().expect("Mime is always valid"))
+    })
+    .await
+    .map_err(|e| format!("Failed to create GD folder {:?}: {}", name, e))?;
+    folder
+        .id
+        .ok_or_else(|| format!("GD did not return an id for folder {:?}", name))
+}
+
+// Walks `subfolder` from the root folder, creating the missing folders on the way
+pub async fn resolve_folder(
+    hub: &DriveHub<DriveConnector>,
+    root_id: &str,
+    subfolder: &str,
+    folders: &SharedFolderCache,
+    retries: u8,
+) -> Result<String, String> {
+    let mut parent_id = root_id.to_string();
+    let mut path = String::new();
+    for segment in subfolder.split('/').filter(|segment| !segment.is_empty()) {
+        path.push('/');
+        path.push_str(segment);
+        if let Some(folder_id) = folders.get(&path) {
+            parent_id = folder_id;
+            continue;
+        }
+        // Keeps concurrent uploads from creating the same folder twice, other folders go on
+        let _creating = folders.creating.lock(&path).await;
+        if let Some(folder_id) = folders.get(&path) {
+            parent_id = folder_id;
+            continue;
+        }
+
+        let query = format!(
+            "name = {} and {} in parents and mimeType = '{}' and trashed = false",
+            quote(segment),
+            quote(&parent_id),
+            FOLDER_MIME_TYPE
+        );
+        let existing = list_files(hub, &query, retries)
+            .await
+            .map_err(|e| format!("Failed to look up GD folder {:?}: {}", path, e))?;
+        let folder_id = match existing.into_iter().find_map(|folder| folder.id) {
+            Some(folder_id) => folder_id,
+            None => create_folder(hub, segment, &parent_id, retries).await?,
+        };
+        folders.insert(&path, &folder_id);
+        parent_id = folder_id;
+    }
+    Ok(parent_id)
+}
+
+async fn find_existing(
+    hub: &DriveHub<DriveConnector>,
+    bytes_result: &Bytes,
+    filename: &str,
+    folder_id: &str,
+    match_by: &DuplicateMatch,
+    retries: u8,
+) -> Result<Option<Existing>, Error> {
+    let in_folder = format!("{} in parents and trashed = false", quote(folder_id));
+    let query = match match_by {
+        DuplicateMatch::Name => format!("name = {} and {}", quote(filename), in_folder),
+        // Drive can't search by checksum, so the whole folder is listed
+        DuplicateMatch::Md5 => format!("mimeType != '{}' and {}", FOLDER_MIME_TYPE, in_folder),
+    };
+    let files = list_files(hub, &query, retries).await?;
+
+    if let DuplicateMatch::Md5 = match_by {
+        let checksum = md5_hex(bytes_result);
+        let same_content = files
+            .iter()
+            .find(|file| file.md5_checksum.as_deref() == Some(checksum.as_str()));
+        if let Some(file) = same_content {
+            return Ok(Some(Existing::SameContent(
+                file.name.clone().unwrap_or_default(),
+            )));
+        }
+    }
+    Ok(files
+        .into_iter()
+        .filter(|file| file.name.as_deref() == Some(filename))
+        .find_map(|file| file.id)
+        .map(Existing::SameName))
+}
+
+// "report.pdf" -> "report (2).pdf", "report (3).pdf", ... whichever is free first
+fn version_name(filename: &str, taken: &HashSet<String>) -> String {
+    let path = Path::new(filename);
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(filename);
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| format!(".{}", ext))
+        .unwrap_or_default();
+    (2..)
+        .map(|version| format!("{} ({}){}", stem, version, ext))
+        .find(|name| !taken.contains(name))
+        .expect("Version numbers are unbounded")
+}
+
+async fn next_version_name(
+    hub: &DriveHub<DriveConnector>,
+    filename: &str,
+    folder_id: &str,
+    retries: u8,
+) -> Result<String, Error> {
+    // `name contains` matches word prefixes and misses taken names, so the folder is listed
+    let query = format!("{} in parents and trashed = false", quote(folder_id));
+    let taken = list_files(hub, &query, retries)
+        .await?
+        .into_iter()
+        .filter_map(|file| file.name)
+        .collect::<HashSet<String>>();
+    Ok(version_name(filename, &taken))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn store_file(
+    hub: &DriveHub<DriveConnector>,
+    bytes_result: &Bytes,
+    filename: &str,
+    folder_id: &str,
+    mime_type: &Mime,
+    on_duplicate: Option<&DuplicatePolicy>,
+    match_by: &DuplicateMatch,
+    retries: u8,
+    uploads: &KeyedLocks,
+) -> Result<UploadOutcome, String> {
+    let policy = match on_duplicate {
+        Some(policy) => policy,
+        None => {
+            let target = UploadTarget::New { name: filename, folder_id };
+            return upload_file(hub, bytes_result, target, mime_type, retries)
+                .await
+                .map(|_| UploadOutcome::Created(filename.to_string()))
+                .map_err(|e| e.to_string());
+        }
+    };
+
+    // The check and the upload must not interleave with another upload of the same name
+    let _same_name = uploads.lock(&format!("{}/{}", folder_id, filename)).await;
+    let existing = find_existing(hub, bytes_result, filename, folder_id, match_by, retries)
+        .await
+        .map_err(|e| format!("Failed to check GD for duplicates of {:?}: {}", filename, e))?;
+    let outcome = match (existing, policy) {
+        (None, _) => {
+            let target = UploadTarget::New { name: filename, folder_id };
+            upload_file(hub, bytes_result, target, mime_type, retries)
+                .await
+                .map_err(|e| e.to_string())?;
+            UploadOutcome::Created(filename.to_string())
+        }
+        (Some(Existing::SameContent(name)), _) => {
+            UploadOutcome::Skipped(format!("{:?} has the same content as {:?}", filename, name))
+        }
+        (Some(Existing::SameName(_)), DuplicatePolicy::Skip) => {
+            UploadOutcome::Skipped(format!("{:?} already exists", filename))
+        }
+        (Some(Existing::SameName(file_id)), DuplicatePolicy::Overwrite) => {
+            let target = UploadTarget::Replace { file_id: &file_id };
+            upload_file(hub, bytes_result, target, mime_type, retries)
+                .await
+                .map_err(|e| e.to_string())?;
+            UploadOutcome::Overwritten(filename.to_string())
+        }
+        (Some(Existing::SameName(_)), DuplicatePolicy::Version) => {
+            let version_name = next_version_name(hub, filename, folder_id, retries)
+                .await
+                .map_err(|e| e.to_string())?;
+            let target = UploadTarget::New { name: &version_name, folder_id };
+            upload_file(hub, bytes_result, target, mime_type, retries)
+                .await
+                .map_err(|e| e.to_string())?;
+            UploadOutcome::Created(version_name)
+        }
+    };
+    Ok(outcome)
+}
+
+async fn upload_file(
+    hub: &DriveHub<DriveConnector>,
+    bytes_result: &Bytes,
+    target: UploadTarget<'_>,
+    mime_type: &Mime,
+    retries: u8,
+) -> Result<(), Error> {
+    let resumable = bytes_result.len() > RESUMABLE_UPLOAD_THRESHOLD;
+    let action = match &target {
+        UploadTarget::New { name, .. } => format!("Upload of {:?} to GD", name),
+        UploadTarget::Replace { file_id } => format!("Overwrite of GD file {}", file_id),
+    };
+
+    with_retries(retries, &action, || async {
+        let upload_mime: Mime = mime_type.to_string().parse().expect("Mime is always valid");
+        let content = Cursor::new(bytes_result);
+        match &target {
+            UploadTarget::New { name, folder_id } => {
+                let file_to_upload = api::File {
+                    parents: Some(vec![folder_id.to_string()]),
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                };
+                let upload_call = hub.files().create(file_to_upload);
+                if resumable {
+                    upload_call.upload_resumable(content, upload_mime).await
+                } else {
+                    upload_call.upload(content, upload_mime).await
+                }
+            }
+            UploadTarget::Replace { file_id } => {
+                let upload_call = hub.files().update(api::File::default(), file_id);
+                if resumable {
+                    upload_call.upload_resumable(content, upload_mime).await
+                } else {
+                    upload_call.upload(content, upload_mime).await
+                }
+            }
+        }
+    })
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::test_server::{MockRequest, MockResponse, MockServer};
+
+    fn no_auth(endpoint: &str) -> DriveSettings {
+        DriveSettings {
+            auth_mode: Some(DriveAuthMode::NoAuth),
+            endpoint: Some(endpoint.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn query_param(request: &MockRequest, name: &str) -> Option<String> {
+        url::form_urlencoded::parse(request.query().as_bytes())
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    // Serves a folder holding `existing` (id, name, md5) files; every upload gets a new id
+    async fn drive_mock(existing: Vec<(&'static str, &'static str, &'static str)>) -> MockServer {
+        MockServer::start(move |request: &MockRequest| {
+            let path = request.path.split('?').next().unwrap_or_default();
+            match (request.method.as_str(), path) {
+                ("GET", "/drive/v3/files") => {
+                    let query = query_param(request, "q").unwrap_or_default();
+                    let files = existing
+                        .iter()
+                        .filter(|(_, name, _)| !query.starts_with("name = ") || query.starts_with(&format!("name = {} ", quote(name))))
+                        .map(|(id, name, md5)| json!({"id": id, "name": name, "md5Checksum": md5}))
+                        .collect::<Vec<Value>>();
+                    MockResponse::new(200).body(json!({ "files": files }).to_string())
+                }
+                ("POST", "/upload/drive/v3/files") => MockResponse::new(200).body(json!({"id": "new-id"}).to_string()),
+                ("PATCH", _) if path.starts_with("/upload/drive/v3/files/") => {
+                    MockResponse::new(200).body(json!({"id": path.rsplit('/').next()}).to_string())
+                }
+                _ => MockResponse::new(404),
+            }
+        })
+        .await
+    }
+
+    fn calls(server: &MockServer) -> Vec<String> {
+        server
+            .requests()
+            .iter()
+            .map(|request| format!("{} {}", request.method, request.path.split('?').next().unwrap_or_default()))
+            .collect()
+    }
+
+    #[test]
+    fn a_missing_key_is_a_config_error() {
+        let missing_key = DriveSettings {
+            key_path: Some(PathBuf::from("/nonexistent/sa.json")),
+            ..Default::default()
+        };
+        assert!(missing_key.check().unwrap_err().contains("/nonexistent/sa.json"));
+        assert!(no_auth("http://localhost/").check().is_ok());
+        if env::var("GOOGLE_DRIVE_KEY_PATH").is_err() {
+            assert!(DriveSettings::default().check().unwrap_err().contains("GOOGLE_DRIVE_KEY_PATH"));
+        }
+
+        let step = json!({"Store":{"GoogleDrive":{"folder_id":"root", "credentials":{"key_path":"/nonexistent/sa.json"}}}});
+        assert!(serde_json::from_value::<crate::parser::NextProcessingStep>(step).is_err());
+    }
+
+    #[tokio::test]
+    async fn a_failed_hub_build_is_tried_again() {
+        let hub = SharedDriveHub::default();
+        let missing_key = DriveSettings {
+            key_path: Some(PathBuf::from("/nonexistent/sa.json")),
+            ..Default::default()
+        };
+        assert!(hub.get(&missing_key).await.is_err());
+        assert!(!hub.0.initialized());
+        assert!(hub.get(&no_auth("http://localhost/")).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn missing_folders_are_created_once() {
+        let server = drive_mock(vec![]).await;
+        let hub = no_auth(&server.url).build_hub().await.unwrap();
+        let folders = SharedFolderCache::default();
+
+        assert_eq!(resolve_folder(&hub, "root", "a/b", &folders, 0).await.unwrap(), "new-id");
+        assert_eq!(resolve_folder(&hub, "root", "a/b", &folders, 0).await.unwrap(), "new-id");
+        assert_eq!(
+            calls(&server),
+            vec![
+                "GET /drive/v3/files",
+                "POST /upload/drive/v3/files",
+                "GET /drive/v3/files",
+                "POST /upload/drive/v3/files",
+            ]
+        );
+        let lookup = query_param(&server.requests()[0], "q").unwrap();
+        assert_eq!(
+            lookup,
+            "name = 'a' and 'root' in parents and mimeType = 'application/vnd.google-apps.folder' and trashed = false"
+        );
+    }
+
+    #[tokio::test]
+    async fn uploads_go_to_the_configured_endpoint() {
+        let server = drive_mock(vec![]).await;
+        let hub = no_auth(&server.url).build_hub().await.unwrap();
+        let outcome = store_file(
+            &hub,
+            &Bytes::from_static(b"content"),
+            "report.pdf",
+            "folder-1",
+            &mime::APPLICATION_PDF,
+            None,
+            &DuplicateMatch::Name,
+            0,
+            &KeyedLocks::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(outcome.to_string(), "Uploaded \"report.pdf\" to GD");
+        assert_eq!(calls(&server), vec!["POST /upload/drive/v3/files"]);
+        let upload = &server.requests()[0];
+        assert_eq!(query_param(upload, "uploadType").as_deref(), Some("multipart"));
+        let body = String::from_utf8_lossy(&upload.body);
+        assert!(body.contains("\"name\":\"report.pdf\"") && body.contains("content"));
+    }
+
+    async fn store_with(server: &MockServer, policy: DuplicatePolicy, match_by: DuplicateMatch) -> UploadOutcome {
+        let hub = no_auth(&server.url).build_hub().await.unwrap();
+        store_file(
+            &hub,
+            &Bytes::from_static(b"content"),
+            "report.pdf",
+            "folder-1",
+            &mime::APPLICATION_PDF,
+            Some(&policy),
+            &match_by,
+            0,
+            &KeyedLocks::default(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn quotes_and_backslashes_are_escaped_in_queries() {
+        assert_eq!(quote("report.pdf"), "'report.pdf'");
+        assert_eq!(quote("it's"), r"'it\'s'");
+        assert_eq!(quote(r"a\b"), r"'a\\b'");
+    }
+
+    #[test]
+    fn md5_is_lowercase_hex() {
+        assert_eq!(md5_hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex(b"content"), "9a0364b9e99bb480dd25e1f0284c8555");
+    }
+
+    #[test]
+    fn versions_take_the_first_free_number() {
+        let taken = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<HashSet<String>>();
+        assert_eq!(version_name("report.pdf", &taken(&[])), "report (2).pdf");
+        assert_eq!(
+            version_name("report.pdf", &taken(&["report.pdf", "report (2).pdf", "report (3).pdf"])),
+            "report (4).pdf"
+        );
+        assert_eq!(version_name("README", &taken(&["README (2)"])), "README (3)");
+        assert_eq!(version_name("archive.tar.gz", &taken(&[])), "archive.tar (2).gz");
+    }
+
+    #[tokio::test]
+    async fn only_network_errors_throttling_and_server_errors_are_retried() {
+        for (status, retryable) in [(503, true), (500, true), (429, true), (404, false), (403, false)] {
+            let server = MockServer::start(move |_: &MockRequest| MockResponse::new(status)).await;
+            let hub = no_auth(&server.url).build_hub().await.unwrap();
+            let error = list_files(&hub, "trashed = false", 0).await.unwrap_err();
+            assert_eq!(is_retryable(&error), retryable, "status {}", status);
+        }
+
+        // Nothing listens on the port of a dropped listener
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let hub = no_auth(&format!("http://{}", closed)).build_hub().await.unwrap();
+        assert!(is_retryable(&list_files(&hub, "trashed = false", 0).await.unwrap_err()));
+
+        assert!(is_retryable(&Error::Io(std::io::Error::other("reset"))));
+        assert!(!is_retryable(&Error::Cancelled));
+        assert!(!is_retryable(&Error::BadRequest(json!({"error": "invalid"}))));
+    }
+
+    #[tokio::test]
+    async fn skip_leaves_an_existing_name_alone() {
+        let server = drive_mock(vec![("file-1", "report.pdf", "")]).await;
+        let outcome = store_with(&server, DuplicatePolicy::Skip, DuplicateMatch::Name).await;
+        assert_eq!(outcome.to_string(), "Skip GD upload: \"report.pdf\" already exists");
+        assert_eq!(calls(&server), vec!["GET /drive/v3/files"]);
+    }
+
+    #[tokio::test]
+    async fn overwrite_replaces_the_existing_file() {
+        let server = drive_mock(vec![("file-1", "report.pdf", "")]).await;
+        let outcome = store_with(&server, DuplicatePolicy::Overwrite, DuplicateMatch::Name).await;
+        assert_eq!(outcome.to_string(), "Overwrote \"report.pdf\" in GD");
+        assert_eq!(calls(&server), vec!["GET /drive/v3/files", "PATCH /upload/drive/v3/files/file-1"]);
+    }
+
+    #[tokio::test]
+    async fn version_uploads_under_the_first_free_name() {
+        let server = drive_mock(vec![
+            ("file-1", "report.pdf", ""),
+            ("file-2", "report (2).pdf", ""),
+            ("file-3", "report-2024 (3).pdf", ""),
+        ])
+        .await;
+        let outcome = store_with(&server, DuplicatePolicy::Version, DuplicateMatch::Name).await;
+        assert_eq!(outcome.to_string(), "Uploaded \"report (3).pdf\" to GD");
+        assert_eq!(
+            calls(&server),
+            vec!["GET /drive/v3/files", "GET /drive/v3/files", "POST /upload/drive/v3/files"]
+        );
+        assert!(String::from_utf8_lossy(&server.requests()[2].body).contains("\"name\":\"report (3).pdf\""));
+    }
+
+    #[tokio::test]
+    async fn same_content_under_another_name_is_skipped() {
+        let server = drive_mock(vec![("file-1", "other.pdf", "9a0364b9e99bb480dd25e1f0284c8555")]).await;
+        let outcome = store_with(&server, DuplicatePolicy::Version, DuplicateMatch::Md5).await;
+        assert_eq!(
+            outcome.to_string(),
+            "Skip GD upload: \"report.pdf\" has the same content as \"other.pdf\""
+        );
+        assert_eq!(calls(&server), vec!["GET /drive/v3/files"]);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fs::{create_dir_all, rename, File, OpenOptions};
+use std::io::{BufWriter, Result as IOResult, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::error;
+use serde_json::Value;
+
+pub type SharedJsonLinesWriter = Arc<Mutex<Option<JsonLinesWriter>>>;
+
+// Lines are buffered, and reach the file at most this long after they were written
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct JsonLinesWriter {
+    path: PathBuf,
+    max_file_bytes: Option<u64>,
+    written: u64,
+    writer: BufWriter<File>,
+    flushed_at: Instant,
+}
+
+fn open_append(path: &Path) -> IOResult<File> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            create_dir_all(parent)?;
+        }
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// results.jsonl -> results.1.jsonl, results.2.jsonl, ...
+fn rotated_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    (1..)
+        .map(|n| path.with_file_name(format!("{}.{}{}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .expect("Unbounded range always yields a free name")
+}
+
+impl JsonLinesWriter {
+    pub fn open(path: &Path, max_file_bytes: Option<u64>) -> IOResult<Self> {
+        let file = open_append(path)?;
+        let written = file.metadata()?.len();
+        Ok(JsonLinesWriter {
+            path: path.to_owned(),
+            max_file_bytes,
+            written,
+            writer: BufWriter::new(file),
+            flushed_at: Instant::now(),
+        })
+    }
+
+    pub fn write_value(&mut self, value: &Value) -> IOResult<usize> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+
+        let line_len = line.len() as u64;
+        if let Some(max) = self.max_file_bytes {
+            if self.written > 0 && self.written + line_len > max {
+                self.rotate()?;
+            }
+        }
+        self.writer.write_all(&line)?;
+        self.written += line_len;
+        if self.flushed_at.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(line.len())
+    }
+
+    pub fn flush(&mut self) -> IOResult<()> {
+        self.writer.flush()?;
+        self.flushed_at = Instant::now();
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> IOResult<()> {
+        self.flush()?;
+        rename(&self.path, rotated_path(&self.path))?;
+        self.writer = BufWriter::new(open_append(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+// The writer is dropped with its storage at the end of the crawl, which writes out the rest
+impl Drop for JsonLinesWriter {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Failed to flush JSON lines to {:?}: {}", self.path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_dir, read_to_string, remove_dir_all};
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("crawler-jsonl-{}", nanoid::nanoid!(8)))
+    }
+
+    #[test]
+    fn rotated_names_skip_the_taken_ones() {
+        let dir = temp_dir();
+        let path = dir.join("results.jsonl");
+        create_dir_all(&dir).unwrap();
+        assert_eq!(rotated_path(&path), dir.join("results.1.jsonl"));
+        File::create(dir.join("results.1.jsonl")).unwrap();
+        assert_eq!(rotated_path(&path), dir.join("results.2.jsonl"));
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn full_files_are_rotated_and_flushed() {
+        let dir = temp_dir();
+        let path = dir.join("results.jsonl");
+        let mut writer = JsonLinesWriter::open(&path, Some(20)).unwrap();
+        writer.write_value(&json!({"n": 1})).unwrap();
+        writer.write_value(&json!({"n": 2})).unwrap();
+        writer.write_value(&json!({"n": 3})).unwrap();
+
+        // Read while the writer is still open
+        assert_eq!(read_to_string(dir.join("results.1.jsonl")).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+        writer.flush().unwrap();
+        assert_eq!(read_to_string(&path).unwrap(), "{\"n\":3}\n");
+        assert_eq!(read_dir(&dir).unwrap().count(), 2);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn buffered_lines_are_written_on_drop() {
+        let dir = temp_dir();
+        let path = dir.join("results.jsonl");
+        let mut writer = JsonLinesWriter::open(&path, None).unwrap();
+        writer.write_value(&json!({"n": 1})).unwrap();
+        writer.write_value(&json!({"n": 2})).unwrap();
+        drop(writer);
+
+        assert_eq!(read_to_string(&path).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+        remove_dir_all(&dir).unwrap();
+    }
+}
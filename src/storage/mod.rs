@@ -0,0 +1,865 @@
+mod content_store;
+mod csv_writer;
+mod filename_template;
+mod google_drive;
+mod json_lines;
+mod s3;
+mod sqlite;
+pub use filename_template::sha256_hex;
+pub use json_lines::JsonLinesWriter;
+
+use content_store::{ContentAddressing, ContentIndex, IndexUpdate, StoredObject};
+use csv_writer::{CsvWriter, SharedCsvWriter};
+use filename_template::{
+    de_optional_template, de_template, ext_from_mime, FilenameTemplate, TemplateInput,
+};
+use google_drive::{
+    DriveSettings, DuplicateMatch, DuplicatePolicy, KeyedLocks, SharedDriveHub, SharedFolderCache,
+    UploadOutcome,
+};
+use json_lines::SharedJsonLinesWriter;
+use s3::SharedS3Client;
+use sqlite::{RowCommit, SharedSqliteWriter, SqliteWriter};
+
+use bytes::Bytes;
+use chrono::Utc;
+use log::Level;
+use nanoid::nanoid;
+use serde::{de, Deserialize, Deserializer};
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::io::Result as IOResult;
+use std::sync::{Arc, Mutex};
+
+use mime::{self, STAR_STAR};
+use std::default::Default;
+use std::fs::create_dir_all;
+use tokio::fs::write;
+
+use crate::logging::log_ctx;
+use crate::parser::{ProcessingResultUnit, ResultContext};
+use crate::sink::CrawlResult;
+use crate::response_adaptor::Resp;
+use crate::stats::StoreOutcome;
+
+#[derive(std::fmt::Debug, Deserialize, Clone, Default)]
+pub enum FileName {
+    RandomNanoid,
+    #[default]
+    Origin,
+}
+
+#[derive(std::fmt::Debug, Deserialize, Clone)]
+pub enum FileExt {
+    MP4,
+    JPEG,
+}
+
+pub const RESULT_META_COLUMNS: [&str; 3] = ["source_url", "timestamp", "step_path"];
+
+pub fn cell_value(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| cell_value(Some(item)))
+            .collect::<Vec<String>>()
+            .join("; "),
+        Some(other) => other.to_string(),
+    }
+}
+
+// Records keep their fields, plain results go to `value`; result metadata is added alongside
+fn result_row(value: Value, ctx: &ResultContext) -> Map<String, Value> {
+    let mut row = match value {
+        Value::Object(record) => record,
+        plain_value => {
+            let mut row = Map::new();
+            row.insert("value".to_string(), plain_value);
+            row
+        }
+    };
+    row.entry("source_url").or_insert_with(|| Value::String(ctx.source_url.to_string()));
+    row.entry("timestamp").or_insert_with(|| Value::String(Utc::now().to_rfc3339()));
+    row.entry("step_path").or_insert_with(|| Value::String(ctx.step_path.clone()));
+    row.entry("provenance").or_insert_with(|| json!(ctx.provenance()));
+    row
+}
+
+// Written next to a stored file, e.g. `photo.jpg.provenance.json`
+fn provenance_sidecar(ctx: &ResultContext) -> Bytes {
+    Bytes::from(serde_json::to_vec_pretty(&ctx.provenance()).expect("Provenance is valid JSON"))
+}
+
+fn provenance_name(name: &str) -> String {
+    format!("{}.provenance.json", name)
+}
+
+// Writers are opened on first use and shared by every task storing through the same step
+fn with_shared_writer<W, T, E>(
+    shared: &Mutex<Option<W>>,
+    open: impl FnOnce() -> Result<W, E>,
+    write: impl FnOnce(&mut W) -> Result<T, E>,
+) -> Result<T, E> {
+    let mut writer_guard = shared.lock().expect("Storage writer lock is poisoned");
+    let writer = match writer_guard.as_mut() {
+        Some(writer) => writer,
+        None => writer_guard.insert(open()?),
+    };
+    write(writer)
+}
+
+// File writers block, so they run on the blocking pool instead of the async worker threads
+async fn write_shared<W, T>(
+    shared: &Arc<Mutex<Option<W>>>,
+    open: impl FnOnce() -> IOResult<W> + Send + 'static,
+    write: impl FnOnce(&mut W) -> IOResult<T> + Send + 'static,
+) -> IOResult<T>
+where
+    W: Send + 'static,
+    T: Send + 'static,
+{
+    let shared = shared.clone();
+    tokio::task::spawn_blocking(move || with_shared_writer(&shared, open, write))
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+}
+
+const SQLITE_COMMIT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// A batch that doesn't fill up in time is committed by whichever row waited longest
+async fn wait_for_commit(writer: &SharedSqliteWriter, mut commit: RowCommit) -> Result<(), String> {
+    if let Ok(committed) = tokio::time::timeout(SQLITE_COMMIT_INTERVAL, &mut commit).await {
+        return committed.unwrap_or_else(|_| Err("Row was dropped before it was committed".to_string()));
+    }
+    // The rows of a failed batch get the error through their commit
+    let _ = write_shared(
+        writer,
+        || Err(std::io::Error::other("SQLite writer is closed")),
+        |sqlite_writer| sqlite_writer.flush().map_err(std::io::Error::other),
+    )
+    .await;
+    commit
+        .await
+        .unwrap_or_else(|_| Err("Row was dropped before it was committed".to_string()))
+}
+
+fn try_local_path(path: &str, or_create: bool) -> Result<PathBuf, String> {
+    let path_buf = PathBuf::from(path);
+    if path_buf.exists() {
+        Ok(path_buf)
+    } else {
+        match or_create {
+            true => match create_dir_all(&path_buf) {
+                Ok(_) => Ok(path_buf),
+                Err(e) => Err(e.to_string()),
+            },
+            false => Err(format!("Directory {:?} doesn't exists...", path)),
+        }
+    }
+}
+
+// Settings that would only fail on the first store are rejected when the config loads
+pub fn de_checked_storage<'de, D>(deserializer: D) -> Result<Storage, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let storage = Storage::deserialize(deserializer)?;
+    if let Storage::GoogleDrive { credentials, .. } = &storage {
+        credentials.check().map_err(de::Error::custom)?;
+    }
+    Ok(storage)
+}
+
+fn de_binary<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let path_map: Map<String, Value> = Deserialize::deserialize(deserializer)?;
+
+    let path_opt = path_map.get("path");
+    let or_create = path_map.get("or_create");
+    match path_opt {
+        Some(path) => {
+            if !path.is_string() {
+                return Err(de::Error::custom(format!(
+                    "Wrong value for 'path', {:?}",
+                    path.to_string()
+                )));
+            }
+
+            let create = match or_create.unwrap_or(&Value::Bool(false)) {
+                Value::Bool(b) => *b,
+                wrong_bool => {
+                    return Err(de::Error::custom(format!(
+                        "Wrong value for 'or_create', {:?}",
+                        wrong_bool.to_string()
+                    )))
+                }
+            };
+
+            match try_local_path(path.as_str().unwrap(), create) {
+                Err(e) => Err(de::Error::custom(e)),
+                Ok(path) => Ok(path),
+            }
+        }
+        None => Err(de::Error::custom(format!(
+            "Path variable has not been passed for directory, {:?}",
+            path_map
+        ))),
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub enum Storage {
+    LocalDrive {
+        #[serde(deserialize_with = "de_binary")]
+        dirname: PathBuf,
+        #[serde(default)]
+        filename_class: FileName,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ext: Option<FileExt>,
+        #[serde(default)]
+        #[serde(deserialize_with = "de_optional_template")]
+        filename_template: Option<FilenameTemplate>,
+        #[serde(default)]
+        content_addressed: Option<ContentAddressing>,
+        #[serde(default)]
+        provenance: bool,
+    },
+    GoogleDrive {
+        folder_id: String,
+        #[serde(default)]
+        #[serde(deserialize_with = "de_optional_template")]
+        subfolder: Option<FilenameTemplate>,
+        #[serde(default)]
+        #[serde(deserialize_with = "de_optional_template")]
+        filename_template: Option<FilenameTemplate>,
+        #[serde(default)]
+        on_duplicate: Option<DuplicatePolicy>,
+        #[serde(default)]
+        match_by: DuplicateMatch,
+        #[serde(default)]
+        credentials: DriveSettings,
+        #[serde(default = "default_upload_retries")]
+        upload_retries: u8,
+        #[serde(default)]
+        provenance: bool,
+        #[serde(skip)]
+        hub: SharedDriveHub,
+        #[serde(skip)]
+        folders: SharedFolderCache,
+        #[serde(skip)]
+        uploads: KeyedLocks,
+    },
+    JsonLines {
+        path: PathBuf,
+        #[serde(default)]
+        max_file_bytes: Option<u64>,
+        #[serde(skip)]
+        writer: SharedJsonLinesWriter,
+    },
+    Csv {
+        path: PathBuf,
+        columns: Vec<String>,
+        #[serde(skip)]
+        writer: SharedCsvWriter,
+    },
+    Sqlite {
+        path: PathBuf,
+        table: String,
+        fields: Vec<String>,
+        #[serde(default)]
+        unique_key: Vec<String>,
+        #[serde(default = "default_batch_size")]
+        batch_size: usize,
+        #[serde(skip)]
+        writer: SharedSqliteWriter,
+    },
+    S3 {
+        #[serde(default)]
+        endpoint: Option<String>,
+        #[serde(default)]
+        region: Option<String>,
+        bucket: String,
+        #[serde(default)]
+        key_prefix: String,
+        #[serde(default = "default_key_template")]
+        #[serde(deserialize_with = "de_template")]
+        key_template: FilenameTemplate,
+        #[serde(default = "default_multipart_threshold")]
+        multipart_threshold: usize,
+        #[serde(default)]
+        provenance: bool,
+        #[serde(skip)]
+        client: SharedS3Client,
+    },
+    // Results go to a channel or callback registered on the Crawler under `name`
+    Channel {
+        name: String,
+    },
+    Callback {
+        name: String,
+    },
+}
+
+fn default_upload_retries() -> u8 {
+    3
+}
+
+fn default_batch_size() -> usize {
+    100
+}
+
+fn default_key_template() -> FilenameTemplate {
+    "{filename}".parse().expect("Default key template is valid")
+}
+
+fn default_multipart_threshold() -> usize {
+    16 * 1024 * 1024
+}
+
+impl Storage {
+    pub async fn store(&self, resp: &Resp, ctx: &ResultContext) {
+        let (bytes_result, filename, mime_type) = match (resp, self) {
+            (Resp::RespText(text), Storage::JsonLines { .. }) => {
+                self.store_json_line(Value::String(text.clone()), ctx).await;
+                return;
+            }
+            (Resp::RespText(text), Storage::Csv { .. }) => {
+                self.store_csv_row(Value::String(text.clone()), ctx).await;
+                return;
+            }
+            (Resp::RespText(text), Storage::Sqlite { .. }) => {
+                self.store_sqlite_row(Value::String(text.clone()), ctx).await;
+                return;
+            }
+            (Resp::RespText(text), Storage::Channel { .. } | Storage::Callback { .. }) => {
+                self.store_in_sink(&ProcessingResultUnit::Str(text.clone()), ctx).await;
+                return;
+            }
+            (
+                Resp::RespBytes {
+                    bts,
+                    filename,
+                    mime_type,
+                },
+                Storage::LocalDrive { .. } | Storage::GoogleDrive { .. } | Storage::S3 { .. },
+            ) => (bts, filename, mime_type),
+            (resp, storage) => {
+                log_ctx!(
+                    Level::Error,
+                    ctx,
+                    "Storage {} cannot store {:?} response from {}",
+                    storage.kind(),
+                    resp.res_type_marker(),
+                    ctx.source_url
+                );
+                self.record(ctx, StoreOutcome::Failed);
+                return;
+            }
+        };
+
+        let template_input = TemplateInput {
+            filename,
+            mime_type: mime_type.as_ref(),
+            bytes: bytes_result,
+            ctx,
+        };
+        match self {
+            Storage::LocalDrive {
+                dirname,
+                filename_class,
+                ext,
+                filename_template,
+                content_addressed,
+                provenance,
+            } => {
+                if let Some(content_addressing) = content_addressed {
+                    self.store_content_addressed(&template_input, dirname, content_addressing)
+                        .await;
+                    return;
+                }
+                let relative_name = match filename_template {
+                    Some(template) => match template.render(&template_input) {
+                        Ok(relative_name) => relative_name,
+                        Err(e) => {
+                            log_ctx!(Level::Error, ctx, "Cannot name file from {}: {}", ctx.source_url, e);
+                            self.record(ctx, StoreOutcome::Failed);
+                            return;
+                        }
+                    },
+                    None => self.prepare_filename(filename, filename_class, ext.as_ref()),
+                };
+                let stored = self.store_local(bytes_result, &relative_name, dirname, ctx).await;
+                if *provenance && stored {
+                    let sidecar = dirname.join(provenance_name(&relative_name));
+                    if let Err(e) = write(&sidecar, provenance_sidecar(ctx)).await {
+                        log_ctx!(Level::Error, ctx, "Failed to write provenance {:?}: {}", sidecar, e);
+                    }
+                }
+            }
+            Storage::GoogleDrive { .. } => {
+                self.store_in_google_drive(&template_input).await;
+            }
+            Storage::S3 { .. } => {
+                self.store_in_s3(&template_input).await;
+            }
+            Storage::JsonLines { .. }
+            | Storage::Csv { .. }
+            | Storage::Sqlite { .. }
+            | Storage::Channel { .. }
+            | Storage::Callback { .. } => {}
+        }
+    }
+
+    pub async fn store_result(&self, result: &ProcessingResultUnit, ctx: &ResultContext) {
+        match self {
+            Storage::JsonLines { .. } => self.store_json_line(result.to_json_value(), ctx).await,
+            Storage::Csv { .. } => self.store_csv_row(result.to_json_value(), ctx).await,
+            Storage::Sqlite { .. } => self.store_sqlite_row(result.to_json_value(), ctx).await,
+            Storage::Channel { .. } | Storage::Callback { .. } => self.store_in_sink(result, ctx).await,
+            storage => {
+                log_ctx!(
+                    Level::Error,
+                    ctx,
+                    "Storage {} cannot store extracted result {:?}",
+                    storage.kind(),
+                    result
+                );
+                self.record(ctx, StoreOutcome::Failed);
+            }
+        }
+    }
+
+    fn record(&self, ctx: &ResultContext, outcome: StoreOutcome) {
+        if let StoreOutcome::Failed = outcome {
+            ctx.mark_failed();
+        }
+        ctx.stats.record_store(self.kind(), outcome);
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Storage::LocalDrive { .. } => "LocalDrive",
+            Storage::GoogleDrive { .. } => "GoogleDrive",
+            Storage::JsonLines { .. } => "JsonLines",
+            Storage::Csv { .. } => "Csv",
+            Storage::Sqlite { .. } => "Sqlite",
+            Storage::S3 { .. } => "S3",
+            Storage::Channel { .. } => "Channel",
+            Storage::Callback { .. } => "Callback",
+        }
+    }
+
+    async fn store_json_line(&self, value: Value, ctx: &ResultContext) {
+        if let Storage::JsonLines {
+            path,
+            max_file_bytes,
+            writer,
+        } = self
+        {
+            let line = json!({
+                "value": value,
+                "source_url": ctx.source_url.as_str(),
+                "timestamp": Utc::now().to_rfc3339(),
+                "step_path": ctx.step_path,
+                "provenance": ctx.provenance(),
+            });
+
+            let (open_path, max_file_bytes) = (path.clone(), *max_file_bytes);
+            let write_result = write_shared(
+                writer,
+                move || JsonLinesWriter::open(&open_path, max_file_bytes),
+                move |json_writer| json_writer.write_value(&line),
+            )
+            .await;
+            match write_result {
+                Ok(bytes) => self.record(ctx, StoreOutcome::Written(bytes)),
+                Err(e) => {
+                    log_ctx!(Level::Error, ctx, "Failed to write JSON line to {:?}: {}", path, e);
+                    self.record(ctx, StoreOutcome::Failed);
+                }
+            }
+        }
+    }
+
+    async fn store_csv_row(&self, value: Value, ctx: &ResultContext) {
+        if let Storage::Csv {
+            path,
+            columns,
+            writer,
+        } = self
+        {
+            let row = result_row(value, ctx);
+            let (open_path, columns) = (path.clone(), columns.clone());
+            let write_result = write_shared(
+                writer,
+                move || CsvWriter::open(&open_path, &columns),
+                move |csv_writer| csv_writer.write_row(&row),
+            )
+            .await;
+            match write_result {
+                Ok(bytes) => self.record(ctx, StoreOutcome::Written(bytes)),
+                Err(e) => {
+                    log_ctx!(Level::Error, ctx, "Failed to write CSV row to {:?}: {}", path, e);
+                    self.record(ctx, StoreOutcome::Failed);
+                }
+            }
+        }
+    }
+
+    // A row counts as written once its batch is committed, so OnlyWhen never records a row that was lost
+    async fn store_sqlite_row(&self, value: Value, ctx: &ResultContext) {
+        if let Storage::Sqlite {
+            path,
+            table,
+            fields,
+            unique_key,
+            batch_size,
+            writer,
+        } = self
+        {
+            let row = result_row(value, ctx);
+            let (open_path, open_table, fields, unique_key, batch_size) =
+                (path.clone(), table.clone(), fields.clone(), unique_key.clone(), *batch_size);
+            let queued = write_shared(
+                writer,
+                move || {
+                    SqliteWriter::open(&open_path, &open_table, &fields, &unique_key, batch_size)
+                        .map_err(std::io::Error::other)
+                },
+                move |sqlite_writer| sqlite_writer.insert_row(&row).map_err(std::io::Error::other),
+            )
+            .await;
+            let write_result = match queued {
+                Ok((bytes, commit)) => wait_for_commit(writer, commit).await.map(|_| bytes),
+                Err(e) => Err(e.to_string()),
+            };
+            match write_result {
+                Ok(bytes) => self.record(ctx, StoreOutcome::Written(bytes)),
+                Err(e) => {
+                    log_ctx!(Level::Error, ctx, "Failed to insert row into {:?} table {}: {}", path, table, e);
+                    self.record(ctx, StoreOutcome::Failed);
+                }
+            }
+        }
+    }
+
+    // Waits until the receiver takes the result, so a slow consumer throttles the crawl
+    async fn store_in_sink(&self, result: &ProcessingResultUnit, ctx: &ResultContext) {
+        let (name, sink) = match self {
+            Storage::Channel { name } => (name, ctx.sinks.channel(name)),
+            Storage::Callback { name } => (name, ctx.sinks.callback(name)),
+            _ => return,
+        };
+        match sink {
+            Some(sink) => {
+                let bytes = result.to_json_value().to_string().len();
+                sink.accept(CrawlResult {
+                    result: result.clone(),
+                    provenance: ctx.provenance(),
+                })
+                .await;
+                self.record(ctx, StoreOutcome::Written(bytes));
+            }
+            None => {
+                log_ctx!(Level::Error, ctx, "No {} named {:?} is registered on the Crawler", self.kind(), name);
+                self.record(ctx, StoreOutcome::Failed);
+            }
+        }
+    }
+
+    pub async fn store_in_s3(&self, input: &TemplateInput<'_>) {
+        if let Storage::S3 {
+            endpoint,
+            region,
+            bucket,
+            key_prefix,
+            key_template,
+            multipart_threshold,
+            provenance,
+            client,
+        } = self
+        {
+            let s3_client = client
+                .get_or_init(|| s3::build_client(endpoint.as_deref(), region.as_deref()))
+                .await;
+            let key = match key_template.render(input) {
+                Ok(key) => format!("{}{}", key_prefix, key),
+                Err(e) => {
+                    log_ctx!(Level::Error, input.ctx, "Cannot build S3 key for {}: {}", input.ctx.source_url, e);
+                    self.record(input.ctx, StoreOutcome::Failed);
+                    return;
+                }
+            };
+            let mime_type = input.mime_type.unwrap_or(&STAR_STAR);
+
+            match s3::put_object(
+                s3_client,
+                bucket,
+                &key,
+                input.bytes,
+                mime_type.as_ref(),
+                *multipart_threshold,
+            )
+            .await
+            {
+                Ok(_) => {
+                    log_ctx!(Level::Info, input.ctx, "Uploaded s3://{}/{}", bucket, key);
+                    self.record(input.ctx, StoreOutcome::Written(input.bytes.len()));
+                    if *provenance {
+                        let sidecar_key = provenance_name(&key);
+                        let sidecar = provenance_sidecar(input.ctx);
+                        let upload = s3::put_object(
+                            s3_client,
+                            bucket,
+                            &sidecar_key,
+                            &sidecar,
+                            mime::APPLICATION_JSON.as_ref(),
+                            *multipart_threshold,
+                        );
+                        if let Err(e) = upload.await {
+                            log_ctx!(Level::Error, input.ctx, "Failed to upload s3://{}/{}: {}", bucket, sidecar_key, e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    log_ctx!(Level::Error, input.ctx, "Failed to upload s3://{}/{}: {}", bucket, key, e);
+                    self.record(input.ctx, StoreOutcome::Failed);
+                }
+            }
+        }
+    }
+
+    pub async fn store_local(
+        &self,
+        bytes_result: &Bytes,
+        relative_name: &str,
+        dest_dir: &Path,
+        ctx: &ResultContext,
+    ) -> bool {
+        let outcome = self.write_local(bytes_result, relative_name, dest_dir, ctx).await;
+        let written = matches!(outcome, StoreOutcome::Written(_));
+        self.record(ctx, outcome);
+        written
+    }
+
+    // Writes the file unless it exists; the caller records the outcome
+    async fn write_local(
+        &self,
+        bytes_result: &Bytes,
+        relative_name: &str,
+        dest_dir: &Path,
+        ctx: &ResultContext,
+    ) -> StoreOutcome {
+        let content_name = dest_dir.join(relative_name);
+        if let Some(parent) = content_name.parent() {
+            if let Err(e) = create_dir_all(parent) {
+                log_ctx!(Level::Error, ctx, "Failed to create directory {:?}: {}", parent, e);
+                return StoreOutcome::Failed;
+            }
+        }
+        if !Path::new(&content_name).exists() {
+            if let Err(e) = write(&content_name, bytes_result).await {
+                log_ctx!(Level::Error, ctx, "Failed to create file with content {:?}", e);
+                StoreOutcome::Failed
+            }
+            else {
+                log_ctx!(Level::Info, ctx, "Created new content in {:?}", content_name);
+                StoreOutcome::Written(bytes_result.len())
+            }
+        } else {
+            log_ctx!(Level::Info, ctx, "Skip {:?}", content_name);
+            StoreOutcome::Skipped
+        }
+    }
+
+    // Files are named by their SHA-256, so the same content is written once whatever URL it came from.
+    // The index only points at objects that are on disk, and a failed index update fails the store.
+    pub async fn store_content_addressed(
+        &self,
+        input: &TemplateInput<'_>,
+        dest_dir: &Path,
+        content_addressing: &ContentAddressing,
+    ) {
+        let sha256 = sha256_hex(input.bytes);
+        let ext = input
+            .mime_type
+            .map(ext_from_mime)
+            .unwrap_or_else(|| "bin".to_string());
+        let object_path = content_store::object_path(&sha256, &ext);
+        let outcome = self.write_local(input.bytes, &object_path, dest_dir, input.ctx).await;
+        if let StoreOutcome::Failed = outcome {
+            self.record(input.ctx, outcome);
+            return;
+        }
+
+        let source_url = input.ctx.source_url.to_string();
+        let stored_object = StoredObject {
+            source_url: source_url.clone(),
+            path: object_path,
+            sha256: sha256.clone(),
+            bytes: input.bytes.len(),
+            mime_type: input.mime_type.map(|mime_type| mime_type.to_string()),
+        };
+        let index_path = content_addressing.index_path(dest_dir);
+        let (open_index_path, manifest_path) = (index_path.clone(), content_addressing.manifest_path(dest_dir));
+        let update_result = write_shared(
+            content_addressing.state(),
+            move || ContentIndex::open(&open_index_path, &manifest_path),
+            move |content_index| content_index.record(&stored_object),
+        )
+        .await;
+        match update_result {
+            Ok(update) => {
+                if let IndexUpdate::Changed(previous) = update {
+                    log_ctx!(Level::Info, input.ctx, "Content of {} changed: {} -> {}", source_url, previous, sha256);
+                }
+                self.record(input.ctx, outcome);
+            }
+            Err(e) => {
+                log_ctx!(Level::Error, input.ctx, "Failed to update content index {:?}: {}", index_path, e);
+                self.record(input.ctx, StoreOutcome::Failed);
+            }
+        }
+    }
+
+    pub async fn store_in_google_drive(&self, input: &TemplateInput<'_>) {
+        if let Storage::GoogleDrive {
+            folder_id,
+            subfolder,
+            filename_template,
+            on_duplicate,
+            match_by,
+            credentials,
+            upload_retries,
+            provenance,
+            hub,
+            folders,
+            uploads,
+        } = self
+        {
+            let drive_hub = match hub.get(credentials).await {
+                Ok(drive_hub) => drive_hub,
+                Err(e) => {
+                    log_ctx!(Level::Error, input.ctx, "Google Drive is not available: {}", e);
+                    self.record(input.ctx, StoreOutcome::Failed);
+                    return;
+                }
+            };
+
+            let rendered = subfolder
+                .as_ref()
+                .map(|template| template.render(input))
+                .transpose()
+                .and_then(|subfolder_path| {
+                    let upload_name = match filename_template {
+                        Some(template) => template.render(input)?,
+                        None => input.filename.to_string(),
+                    };
+                    Ok((subfolder_path.unwrap_or_default(), upload_name))
+                });
+            let (subfolder_path, upload_name) = match rendered {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    log_ctx!(Level::Error, input.ctx, "Cannot name GD upload of {:?}: {}", input.filename, e);
+                    self.record(input.ctx, StoreOutcome::Failed);
+                    return;
+                }
+            };
+            // Directories in the rendered name become subfolders, Drive names can't hold them
+            let (name_dirs, filename) = upload_name.rsplit_once('/').unwrap_or(("", &upload_name));
+            let folder_path = format!("{}/{}", subfolder_path, name_dirs);
+            let target_folder = match google_drive::resolve_folder(
+                drive_hub,
+                folder_id,
+                &folder_path,
+                folders,
+                *upload_retries,
+            )
+            .await
+            {
+                Ok(target_folder) => target_folder,
+                Err(e) => {
+                    log_ctx!(Level::Error, input.ctx, "Failed to upload {:?} to GD: {}", filename, e);
+                    self.record(input.ctx, StoreOutcome::Failed);
+                    return;
+                }
+            };
+
+            match google_drive::store_file(
+                drive_hub,
+                input.bytes,
+                filename,
+                &target_folder,
+                input.mime_type.unwrap_or(&STAR_STAR),
+                on_duplicate.as_ref(),
+                match_by,
+                *upload_retries,
+                uploads,
+            )
+            .await
+            {
+                Ok(outcome) => {
+                    log_ctx!(Level::Info, input.ctx, "{}", outcome);
+                    let store_outcome = match outcome {
+                        UploadOutcome::Skipped(_) => StoreOutcome::Skipped,
+                        UploadOutcome::Created(_) | UploadOutcome::Overwritten(_) => {
+                            StoreOutcome::Written(input.bytes.len())
+                        }
+                    };
+                    let stored = matches!(store_outcome, StoreOutcome::Written(_));
+                    self.record(input.ctx, store_outcome);
+                    if *provenance && stored {
+                        let sidecar_name = provenance_name(filename);
+                        let sidecar = provenance_sidecar(input.ctx);
+                        let upload = google_drive::store_file(
+                            drive_hub,
+                            &sidecar,
+                            &sidecar_name,
+                            &target_folder,
+                            &mime::APPLICATION_JSON,
+                            on_duplicate.as_ref(),
+                            match_by,
+                            *upload_retries,
+                            uploads,
+                        );
+                        if let Err(e) = upload.await {
+                            log_ctx!(Level::Error, input.ctx, "Failed to upload {:?} to GD: {}", sidecar_name, e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    log_ctx!(Level::Error, input.ctx, "Failed to upload {:?} to GD: {}", filename, e);
+                    self.record(input.ctx, StoreOutcome::Failed);
+                }
+            }
+        }
+    }
+
+    pub fn prepare_filename(
+        &self,
+        filename: &str,
+        filename_class: &FileName,
+        ext: Option<&FileExt>,
+    ) -> String {
+        let mut new_filename = match filename_class {
+            FileName::Origin => filename.to_owned(),
+            FileName::RandomNanoid => {
+                nanoid!(10)
+            }
+        };
+        if let Some(file_ext) = ext {
+            {
+                let file_ext_str = match file_ext {
+                    FileExt::JPEG => ".jpeg",
+                    FileExt::MP4 => ".mp4",
+                };
+                new_filename.push_str(file_ext_str);
+            }
+        }
+        new_filename
+    }
+}
@@ -0,0 +1,280 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use aws_sdk_s3::config::{Builder as S3ConfigBuilder, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+use tokio::sync::OnceCell;
+
+pub type SharedS3Client = Arc<OnceCell<Client>>;
+
+// S3 rejects multipart chunks below 5 MiB, except for the last one
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+pub async fn build_client(endpoint: Option<&str>, region: Option<&str>) -> Client {
+    // Credentials and defaults come from the environment (AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, AWS_REGION, ...)
+    let sdk_config = aws_config::load_from_env().await;
+    let mut config = S3ConfigBuilder::from(&sdk_config);
+    if let Some(region) = region {
+        config = config.region(Region::new(region.to_string()));
+    }
+    if let Some(endpoint) = endpoint {
+        // MinIO and most S3-compatible servers don't serve virtual-hosted buckets
+        config = config.endpoint_url(endpoint).force_path_style(true);
+    }
+    Client::from_conf(config.build())
+}
+
+pub async fn put_object(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    bytes_result: &Bytes,
+    content_type: &str,
+    multipart_threshold: usize,
+) -> Result<(), String> {
+    if bytes_result.len() <= part_size(multipart_threshold) {
+        return client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(bytes_result.clone()))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string());
+    }
+
+    let upload = client
+        .create_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .content_type(content_type)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let upload_id = upload
+        .upload_id()
+        .ok_or_else(|| "S3 did not return a multipart upload id".to_string())?;
+
+    let parts_result = upload_parts(client, bucket, key, upload_id, bytes_result, multipart_threshold).await;
+    match parts_result {
+        Ok(parts) => client
+            .complete_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        Err(e) => {
+            // Leftover parts are billed until the upload is aborted
+            let _ = client
+                .abort_multipart_upload()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+            Err(e)
+        }
+    }
+}
+
+// The threshold is also the part size, raised to the 5 MiB minimum
+fn part_size(multipart_threshold: usize) -> usize {
+    multipart_threshold.max(MIN_PART_SIZE)
+}
+
+fn part_ranges(len: usize, part_size: usize) -> Vec<Range<usize>> {
+    (0..len)
+        .step_by(part_size)
+        .map(|start| start..(start + part_size).min(len))
+        .collect()
+}
+
+async fn upload_parts(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    bytes_result: &Bytes,
+    multipart_threshold: usize,
+) -> Result<Vec<CompletedPart>, String> {
+    let mut parts = vec![];
+    for (index, range) in part_ranges(bytes_result.len(), part_size(multipart_threshold))
+        .into_iter()
+        .enumerate()
+    {
+        let part_number = index as i32 + 1;
+        let uploaded = client
+            .upload_part()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(bytes_result.slice(range)))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        parts.push(
+            CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(uploaded.e_tag().map(str::to_string))
+                .build(),
+        );
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_s3::config::{BehaviorVersion, Credentials, RequestChecksumCalculation};
+
+    use super::*;
+    use crate::test_server::{MockRequest, MockResponse, MockServer};
+
+    const MIB: usize = 1024 * 1024;
+
+    fn test_client(endpoint: &str) -> Client {
+        let config = S3ConfigBuilder::new()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            // Keeps request bodies plain, so the mock sees the bytes that were sent
+            .request_checksum_calculation(RequestChecksumCalculation::WhenRequired)
+            .build();
+        Client::from_conf(config)
+    }
+
+    // Answers like S3 does, except that part `failing_part` is rejected
+    async fn s3_mock(failing_part: Option<&'static str>) -> MockServer {
+        MockServer::start(move |request: &MockRequest| {
+            let query = request.query();
+            match request.method.as_str() {
+                "POST" if query.starts_with("uploads") => MockResponse::new(200).body(
+                    "<InitiateMultipartUploadResult><Bucket>crawl</Bucket><Key>big.bin</Key>\
+                     <UploadId>upload-1</UploadId></InitiateMultipartUploadResult>",
+                ),
+                "PUT" if failing_part.is_some_and(|part| query.contains(&format!("partNumber={}&", part))) => {
+                    MockResponse::new(400).body("<Error><Code>InvalidPart</Code><Message>rejected</Message></Error>")
+                }
+                "PUT" => MockResponse::new(200).header("ETag", "\"etag\""),
+                "POST" => MockResponse::new(200).body(
+                    "<CompleteMultipartUploadResult><Bucket>crawl</Bucket><Key>big.bin</Key>\
+                     <ETag>\"etag\"</ETag></CompleteMultipartUploadResult>",
+                ),
+                "DELETE" => MockResponse::new(204),
+                _ => MockResponse::new(405),
+            }
+        })
+        .await
+    }
+
+    fn summary(requests: &[MockRequest]) -> Vec<(String, usize)> {
+        requests
+            .iter()
+            .map(|request| (request.method.clone(), request.body.len()))
+            .collect()
+    }
+
+    #[test]
+    fn parts_cover_the_body_and_respect_the_minimum_size() {
+        assert_eq!(part_size(MIB), MIN_PART_SIZE);
+        assert_eq!(part_size(16 * MIB), 16 * MIB);
+        assert_eq!(
+            part_ranges(12 * MIB, part_size(MIB)),
+            vec![0..5 * MIB, 5 * MIB..10 * MIB, 10 * MIB..12 * MIB]
+        );
+        assert_eq!(part_ranges(10 * MIB, 5 * MIB), vec![0..5 * MIB, 5 * MIB..10 * MIB]);
+        assert!(part_ranges(0, 5 * MIB).is_empty());
+    }
+
+    #[tokio::test]
+    async fn small_files_are_put_in_one_request() {
+        let server = s3_mock(None).await;
+        let client = test_client(&server.url);
+        // Below the 5 MiB minimum, whatever the threshold says
+        let bytes = Bytes::from(vec![7; 2 * MIB]);
+        put_object(&client, "crawl", "small.bin", &bytes, "application/octet-stream", MIB)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(summary(&requests), vec![("PUT".to_string(), 2 * MIB)]);
+        assert!(requests[0].path.starts_with("/crawl/small.bin?"));
+    }
+
+    #[tokio::test]
+    async fn large_files_are_uploaded_in_parts() {
+        let server = s3_mock(None).await;
+        let client = test_client(&server.url);
+        let bytes = Bytes::from(vec![7; 11 * MIB]);
+        put_object(&client, "crawl", "big.bin", &bytes, "application/octet-stream", MIB)
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(
+            summary(&requests),
+            vec![
+                ("POST".to_string(), 0),
+                ("PUT".to_string(), 5 * MIB),
+                ("PUT".to_string(), 5 * MIB),
+                ("PUT".to_string(), MIB),
+                ("POST".to_string(), requests[4].body.len()),
+            ]
+        );
+        assert!(requests[3].query().contains("partNumber=3&uploadId=upload-1"));
+        let completion = String::from_utf8_lossy(&requests[4].body);
+        assert_eq!(completion.matches("<Part>").count(), 3);
+    }
+
+    #[tokio::test]
+    async fn a_failed_part_aborts_the_upload() {
+        let server = s3_mock(Some("2")).await;
+        let client = test_client(&server.url);
+        let bytes = Bytes::from(vec![7; 11 * MIB]);
+        let uploaded = put_object(&client, "crawl", "big.bin", &bytes, "application/octet-stream", MIB).await;
+
+        assert!(uploaded.is_err());
+        let methods = server
+            .requests()
+            .iter()
+            .map(|request| request.method.clone())
+            .collect::<Vec<String>>();
+        assert_eq!(methods, vec!["POST", "PUT", "PUT", "DELETE"]);
+        assert!(server.requests()[3].query().contains("uploadId=upload-1"));
+    }
+
+    // Needs a running MinIO, see "S3-compatible storage" in the README:
+    // S3_TEST_ENDPOINT=http://localhost:9000 AWS_ACCESS_KEY_ID=minio AWS_SECRET_ACCESS_KEY=minio123 \
+    //     cargo test minio -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn uploads_reach_minio() {
+        let endpoint = std::env::var("S3_TEST_ENDPOINT").unwrap_or_else(|_| "http://localhost:9000".to_string());
+        let client = build_client(Some(&endpoint), Some("us-east-1")).await;
+        let alphabet = "abcdefghijklmnopqrstuvwxyz0123456789".chars().collect::<Vec<char>>();
+        let bucket = format!("crawler-test-{}", nanoid::nanoid!(8, &alphabet));
+        client.create_bucket().bucket(&bucket).send().await.unwrap();
+
+        for (key, len) in [("small.bin", MIB), ("big.bin", 11 * MIB)] {
+            let bytes = Bytes::from(vec![7; len]);
+            put_object(&client, &bucket, key, &bytes, "application/octet-stream", MIB)
+                .await
+                .unwrap();
+            let head = client.head_object().bucket(&bucket).key(key).send().await.unwrap();
+            assert_eq!(head.content_length(), Some(len as i64));
+            client.delete_object().bucket(&bucket).key(key).send().await.unwrap();
+        }
+        client.delete_bucket().bucket(&bucket).send().await.unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+use std::fs::create_dir_all;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use log::error;
+use rusqlite::{params_from_iter, Connection, Result as SqliteResult};
+use serde_json::{Map, Value};
+use tokio::sync::oneshot;
+
+use super::{cell_value, RESULT_META_COLUMNS};
+
+pub type SharedSqliteWriter = Arc<Mutex<Option<SqliteWriter>>>;
+
+// Resolves once the batch holding the row is committed, or with the reason it wasn't
+pub type RowCommit = oneshot::Receiver<Result<(), String>>;
+
+type PendingRow = (Vec<Option<String>>, oneshot::Sender<Result<(), String>>);
+
+#[derive(Debug)]
+pub struct SqliteWriter {
+    connection: Connection,
+    columns: Vec<String>,
+    insert_sql: String,
+    batch_size: usize,
+    pending: Vec<PendingRow>,
+}
+
+// Missing fields are bound as NULL, so rows lacking a unique_key field never collide
+fn sql_value(value: Option<&Value>) -> Option<String> {
+    match value {
+        None | Some(Value::Null) => None,
+        value => Some(cell_value(value)),
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn table_columns(fields: &[String]) -> Vec<String> {
+    let mut columns = fields.to_vec();
+    RESULT_META_COLUMNS
+        .iter()
+        .filter(|meta| !fields.iter().any(|field| field == *meta))
+        .for_each(|meta| columns.push(meta.to_string()));
+    columns
+}
+
+fn create_table_sql(table: &str, columns: &[String], unique_key: &[String]) -> String {
+    let mut definitions = columns
+        .iter()
+        .map(|column| format!("{} TEXT", quote_identifier(column)))
+        .collect::<Vec<String>>();
+    if !unique_key.is_empty() {
+        let key_columns = unique_key.iter().map(|key| quote_identifier(key)).collect::<Vec<String>>();
+        definitions.push(format!("UNIQUE ({})", key_columns.join(", ")));
+    }
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})",
+        quote_identifier(table),
+        definitions.join(", ")
+    )
+}
+
+fn insert_sql(table: &str, columns: &[String], unique_key: &[String]) -> String {
+    let column_list = columns.iter().map(|column| quote_identifier(column)).collect::<Vec<String>>();
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let mut sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        quote_identifier(table),
+        column_list.join(", "),
+        placeholders
+    );
+
+    // Re-crawled items update the row stored under the same key instead of duplicating it
+    if !unique_key.is_empty() {
+        let key_columns = unique_key.iter().map(|key| quote_identifier(key)).collect::<Vec<String>>();
+        let updates = column_list
+            .iter()
+            .filter(|column| !key_columns.contains(column))
+            .map(|column| format!("{} = excluded.{}", column, column))
+            .collect::<Vec<String>>();
+        if updates.is_empty() {
+            sql.push_str(&format!(" ON CONFLICT ({}) DO NOTHING", key_columns.join(", ")));
+        } else {
+            sql.push_str(&format!(
+                " ON CONFLICT ({}) DO UPDATE SET {}",
+                key_columns.join(", "),
+                updates.join(", ")
+            ));
+        }
+    }
+    sql
+}
+
+impl SqliteWriter {
+    pub fn open(
+        path: &Path,
+        table: &str,
+        fields: &[String],
+        unique_key: &[String],
+        batch_size: usize,
+    ) -> SqliteResult<Self> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                let _ = create_dir_all(parent);
+            }
+        }
+        let connection = Connection::open(path)?;
+        let columns = table_columns(fields);
+        connection.execute(&create_table_sql(table, &columns, unique_key), [])?;
+        // An existing table without the unique_key constraint fails here instead of on every batch
+        let insert_sql = insert_sql(table, &columns, unique_key);
+        connection.prepare_cached(&insert_sql)?;
+
+        Ok(SqliteWriter {
+            connection,
+            insert_sql,
+            columns,
+            batch_size: batch_size.max(1),
+            pending: vec![],
+        })
+    }
+
+    // Returns the bytes of cell text queued for insertion, and the commit of the row to wait for
+    pub fn insert_row(&mut self, row: &Map<String, Value>) -> SqliteResult<(usize, RowCommit)> {
+        let values = self
+            .columns
+            .iter()
+            .map(|column| sql_value(row.get(column)))
+            .collect::<Vec<Option<String>>>();
+        let bytes = values.iter().flatten().map(String::len).sum();
+        let (committed, commit) = oneshot::channel();
+        self.pending.push((values, committed));
+
+        if self.pending.len() >= self.batch_size {
+            self.flush()?;
+        }
+        Ok((bytes, commit))
+    }
+
+    // The batch is taken even when writing it fails, so later inserts never retry it.
+    // Every queued row hears whether it was committed.
+    pub fn flush(&mut self) -> SqliteResult<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let (batch, commits): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending).into_iter().unzip();
+        let written = self.write_batch(&batch);
+        match &written {
+            Ok(row_results) => commits
+                .into_iter()
+                .zip(row_results.iter().cloned())
+                .for_each(|(commit, row_result)| {
+                    let _ = commit.send(row_result);
+                }),
+            Err(e) => commits.into_iter().for_each(|commit| {
+                let _ = commit.send(Err(e.to_string()));
+            }),
+        }
+        written.map(|_| ())
+    }
+
+    fn write_batch(&mut self, batch: &[Vec<Option<String>>]) -> SqliteResult<Vec<Result<(), String>>> {
+        let transaction = self.connection.transaction()?;
+        let mut row_results = Vec::with_capacity(batch.len());
+        {
+            let mut statement = transaction.prepare_cached(&self.insert_sql)?;
+            for values in batch {
+                // A rejected row is skipped, the rest of the batch is still written
+                let row_result = match statement.execute(params_from_iter(values.iter())) {
+                    Ok(_) => Ok(()),
+                    Err(e) => {
+                        error!("Skip SQLite row that failed to insert: {}", e);
+                        Err(e.to_string())
+                    }
+                };
+                row_results.push(row_result);
+            }
+        }
+        transaction.commit()?;
+        Ok(row_results)
+    }
+}
+
+impl Drop for SqliteWriter {
+    fn drop(&mut self) {
+        let rows = self.pending.len();
+        if let Err(e) = self.flush() {
+            error!("Failed to flush {} pending SQLite rows: {}", rows, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn key(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn count_rows(writer: &SqliteWriter, sql: &str) -> i64 {
+        writer.connection.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    fn row(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn upsert_updates_everything_but_the_key() {
+        assert_eq!(
+            insert_sql("items", &key(&["id", "title", "price"]), &key(&["id"])),
+            "INSERT INTO \"items\" (\"id\", \"title\", \"price\") VALUES (?, ?, ?) \
+             ON CONFLICT (\"id\") DO UPDATE SET \"title\" = excluded.\"title\", \"price\" = excluded.\"price\""
+        );
+        assert_eq!(
+            insert_sql("items", &key(&["id"]), &key(&["id"])),
+            "INSERT INTO \"items\" (\"id\") VALUES (?) ON CONFLICT (\"id\") DO NOTHING"
+        );
+        assert_eq!(
+            insert_sql("it\"ems", &key(&["id"]), &[]),
+            "INSERT INTO \"it\"\"ems\" (\"id\") VALUES (?)"
+        );
+    }
+
+    #[test]
+    fn rows_are_upserted_by_key_and_missing_keys_stay_apart() {
+        let mut writer = SqliteWriter::open(Path::new(":memory:"), "items", &key(&["id", "title"]), &key(&["id"]), 10).unwrap();
+        writer.insert_row(&row(json!({"id": "1", "title": "old"}))).unwrap();
+        writer.insert_row(&row(json!({"id": "1", "title": "new"}))).unwrap();
+        writer.insert_row(&row(json!({"title": "no id"}))).unwrap();
+        writer.insert_row(&row(json!({"title": "no id either"}))).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(count_rows(&writer, "SELECT COUNT(*) FROM items"), 3);
+        assert_eq!(count_rows(&writer, "SELECT COUNT(*) FROM items WHERE id IS NULL"), 2);
+        let title: String = writer
+            .connection
+            .query_row("SELECT title FROM items WHERE id = '1'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(title, "new");
+    }
+
+    #[test]
+    fn rejected_rows_are_skipped_and_not_retried() {
+        let mut writer = SqliteWriter::open(Path::new(":memory:"), "items", &key(&["id", "title"]), &[], 2).unwrap();
+        writer
+            .connection
+            .execute("CREATE TRIGGER no_bad BEFORE INSERT ON items WHEN NEW.title = 'bad' BEGIN SELECT RAISE(ABORT, 'bad row'); END", [])
+            .unwrap();
+        let (_, mut bad) = writer.insert_row(&row(json!({"id": "1", "title": "bad"}))).unwrap();
+        let (_, mut good) = writer.insert_row(&row(json!({"id": "2", "title": "good"}))).unwrap();
+        assert!(writer.pending.is_empty());
+        assert!(bad.try_recv().unwrap().is_err());
+        assert_eq!(good.try_recv().unwrap(), Ok(()));
+
+        writer.insert_row(&row(json!({"id": "3", "title": "good"}))).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(count_rows(&writer, "SELECT COUNT(*) FROM items"), 2);
+    }
+
+    #[test]
+    fn rows_are_committed_only_when_their_batch_is() {
+        let mut writer = SqliteWriter::open(Path::new(":memory:"), "items", &key(&["id"]), &[], 10).unwrap();
+        let (bytes, mut commit) = writer.insert_row(&row(json!({"id": "12"}))).unwrap();
+        assert_eq!(bytes, 2);
+        assert!(commit.try_recv().is_err());
+
+        writer.flush().unwrap();
+        assert_eq!(commit.try_recv().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn a_failed_batch_fails_every_row_in_it() {
+        let mut writer = SqliteWriter::open(Path::new(":memory:"), "items", &key(&["id"]), &[], 10).unwrap();
+        let (_, mut first) = writer.insert_row(&row(json!({"id": "1"}))).unwrap();
+        let (_, mut second) = writer.insert_row(&row(json!({"id": "2"}))).unwrap();
+        // A transaction left open makes the batch's own transaction fail to start
+        writer.connection.execute_batch("BEGIN").unwrap();
+
+        assert!(writer.flush().is_err());
+        assert!(first.try_recv().unwrap().is_err());
+        assert!(second.try_recv().unwrap().is_err());
+        assert!(writer.pending.is_empty());
+    }
+
+    #[test]
+    fn table_without_the_unique_key_fails_on_open() {
+        let path = std::env::temp_dir().join(format!("crawler-sqlite-{}.db", nanoid::nanoid!(8)));
+        Connection::open(&path)
+            .unwrap()
+            .execute("CREATE TABLE items (id TEXT, source_url TEXT, step_path TEXT, timestamp TEXT)", [])
+            .unwrap();
+        let opened = SqliteWriter::open(&path, "items", &key(&["id"]), &key(&["id"]), 1);
+        std::fs::remove_file(&path).unwrap();
+        assert!(opened.is_err());
+    }
+}
@@ -0,0 +1,151 @@
+// A local HTTP server for tests that talk to remote APIs; each request gets a canned response
+use std::io::Result as IOResult;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    // Path with the query string
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query(&self) -> &str {
+        self.path.split_once('?').map(|(_, query)| query).unwrap_or_default()
+    }
+}
+
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn new(status: u16) -> Self {
+        MockResponse {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+type Handler = dyn Fn(&MockRequest) -> MockResponse + Send + Sync;
+
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    // Runs until the test's runtime shuts down
+    pub async fn start(handler: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Bind mock server");
+        let url = format!("http://{}", listener.local_addr().expect("Mock server address"));
+        let requests = Arc::new(Mutex::new(vec![]));
+        let handler: Arc<Handler> = Arc::new(handler);
+        let served = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let (handler, served) = (handler.clone(), served.clone());
+                tokio::spawn(async move {
+                    let _ = respond(socket, handler.as_ref(), &served).await;
+                });
+            }
+        });
+        MockServer { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn read_chunked(reader: &mut BufReader<&mut TcpStream>) -> IOResult<Vec<u8>> {
+    let mut body = vec![];
+    loop {
+        let mut size_line = String::new();
+        reader.read_line(&mut size_line).await?;
+        let size_hex = size_line.trim().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size_hex, 16).unwrap_or_default();
+        let mut chunk = vec![0; size + 2];
+        reader.read_exact(&mut chunk).await?;
+        if size == 0 {
+            return Ok(body);
+        }
+        body.extend_from_slice(&chunk[..size]);
+    }
+}
+
+async fn respond(mut socket: TcpStream, handler: &Handler, served: &Mutex<Vec<MockRequest>>) -> IOResult<()> {
+    let request = {
+        let mut reader = BufReader::new(&mut socket);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut headers = vec![];
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).await? <= 2 {
+                break;
+            }
+            if let Some((name, value)) = header.trim_end().split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        let mut request = MockRequest {
+            method,
+            path,
+            headers,
+            body: vec![],
+        };
+        if request.header("transfer-encoding") == Some("chunked") {
+            request.body = read_chunked(&mut reader).await?;
+        } else {
+            let length = request
+                .header("content-length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or_default();
+            request.body = vec![0; length];
+            reader.read_exact(&mut request.body).await?;
+        }
+        request
+    };
+
+    let response = handler(&request);
+    served.lock().unwrap().push(request);
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response.body.len()));
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(&response.body).await?;
+    socket.shutdown().await
+}
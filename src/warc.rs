@@ -0,0 +1,480 @@
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{Result as IOResult, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::error;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, LOCATION, TRANSFER_ENCODING};
+use reqwest::{Method, Version};
+use serde::Deserialize;
+use url::{Host, Url};
+use uuid::Uuid;
+
+use crate::response_adaptor::FetchedResponse;
+use crate::storage::sha256_hex;
+
+pub type SharedWarcWriter = Arc<Mutex<Option<WarcWriter>>>;
+
+const CDX_HEADER: &str = " CDX N b a m s k r M S V g\n";
+
+// Raw request/response pairs of every fetched URL, written as gzip-compressed WARC files
+#[derive(Debug, Deserialize, Clone)]
+pub struct WarcCapture {
+    dir: PathBuf,
+    #[serde(default = "default_prefix")]
+    prefix: String,
+    #[serde(default = "default_max_file_bytes")]
+    max_file_bytes: u64,
+    #[serde(default = "default_cdx")]
+    cdx: bool,
+    #[serde(skip)]
+    writer: SharedWarcWriter,
+}
+
+fn default_prefix() -> String {
+    "crawl".to_string()
+}
+
+fn default_max_file_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_cdx() -> bool {
+    true
+}
+
+#[derive(Debug, Clone)]
+pub struct ExchangeRequest {
+    pub method: Method,
+    pub url: Url,
+    pub headers: HeaderMap,
+}
+
+fn version_str(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "HTTP/1.1",
+    }
+}
+
+fn push_headers(block: &mut Vec<u8>, headers: &HeaderMap) {
+    for (name, value) in headers {
+        // The stored body is already de-chunked, so replay tools must not parse chunks again
+        if name == TRANSFER_ENCODING {
+            block.extend_from_slice(b"x-crawler-");
+        }
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+    block.extend_from_slice(b"\r\n");
+}
+
+fn request_block(request: &ExchangeRequest) -> Vec<u8> {
+    let mut target = request.url.path().to_string();
+    if let Some(query) = request.url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    let mut block = format!("{} {} HTTP/1.1\r\n", request.method, target).into_bytes();
+    if !request.headers.contains_key("host") {
+        if let Some(host) = request.url.host_str() {
+            block.extend_from_slice(format!("host: {}\r\n", host).as_bytes());
+        }
+    }
+    push_headers(&mut block, &request.headers);
+    block
+}
+
+fn response_block(response: &FetchedResponse) -> Vec<u8> {
+    let mut block = format!(
+        "{} {} {}\r\n",
+        version_str(response.version),
+        response.status.as_u16(),
+        response.status.canonical_reason().unwrap_or_default()
+    )
+    .into_bytes();
+    push_headers(&mut block, &response.headers);
+    block.extend_from_slice(&response.body);
+    block
+}
+
+// http://www.Example.com/a?b=1 -> com,example)/a?b=1, http://example.com:8080/ -> com,example:8080)/
+fn surt(url: &Url) -> String {
+    let host = match url.host() {
+        Some(Host::Domain(domain)) => {
+            let domain = domain.to_lowercase();
+            let mut labels = domain
+                .strip_prefix("www.")
+                .unwrap_or(&domain)
+                .split('.')
+                .map(str::to_string)
+                .collect::<Vec<String>>();
+            labels.reverse();
+            labels.join(",")
+        }
+        // IP addresses are kept as they are
+        Some(ip) => ip.to_string(),
+        None => String::new(),
+    };
+    // Default ports are dropped by the URL parser, other ports tell sites apart
+    let port = url.port().map(|port| format!(":{}", port)).unwrap_or_default();
+    let mut key = format!("{}{}){}", host, port, url.path());
+    if let Some(query) = url.query() {
+        key.push('?');
+        key.push_str(query);
+    }
+    key.to_lowercase()
+}
+
+fn record_id() -> String {
+    format!("<urn:uuid:{}>", Uuid::new_v4())
+}
+
+struct WarcRecord<'a> {
+    warc_type: &'a str,
+    record_id: &'a str,
+    date: &'a DateTime<Utc>,
+    target_uri: Option<&'a Url>,
+    content_type: &'a str,
+    extra_headers: Vec<(&'a str, String)>,
+    block: &'a [u8],
+}
+
+impl<'a> WarcRecord<'a> {
+    // Every record is its own gzip member, so readers can seek to a CDX offset directly
+    fn to_gzip(&self) -> IOResult<Vec<u8>> {
+        let mut head = format!(
+            "WARC/1.1\r\nWARC-Type: {}\r\nWARC-Record-ID: {}\r\nWARC-Date: {}\r\n",
+            self.warc_type,
+            self.record_id,
+            self.date.format("%Y-%m-%dT%H:%M:%SZ")
+        );
+        if let Some(uri) = self.target_uri {
+            head.push_str(&format!("WARC-Target-URI: {}\r\n", uri));
+        }
+        for (name, value) in &self.extra_headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!(
+            "Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            self.content_type,
+            self.block.len()
+        ));
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(head.as_bytes())?;
+        encoder.write_all(self.block)?;
+        encoder.write_all(b"\r\n\r\n")?;
+        encoder.finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct WarcWriter {
+    dir: PathBuf,
+    prefix: String,
+    max_file_bytes: u64,
+    serial: usize,
+    file_name: String,
+    file: File,
+    written: u64,
+    // Only the warcinfo record was written to the current file
+    fresh: bool,
+    cdx: Option<File>,
+}
+
+fn open_cdx(path: &Path) -> IOResult<File> {
+    let mut cdx = OpenOptions::new().create(true).append(true).open(path)?;
+    if cdx.metadata()?.len() == 0 {
+        cdx.write_all(CDX_HEADER.as_bytes())?;
+    }
+    Ok(cdx)
+}
+
+impl WarcWriter {
+    pub fn open(capture: &WarcCapture) -> IOResult<Self> {
+        create_dir_all(&capture.dir)?;
+        let cdx = match capture.cdx {
+            true => Some(open_cdx(&capture.dir.join(format!("{}.cdx", capture.prefix)))?),
+            false => None,
+        };
+        let (file_name, file, written) = Self::new_file(&capture.dir, &capture.prefix, 0)?;
+        let mut writer = WarcWriter {
+            dir: capture.dir.clone(),
+            prefix: capture.prefix.clone(),
+            max_file_bytes: capture.max_file_bytes,
+            serial: 0,
+            file_name,
+            file,
+            written,
+            fresh: true,
+            cdx,
+        };
+        writer.write_warcinfo()?;
+        Ok(writer)
+    }
+
+    // crawl-20260101120000-00000.warc.gz, crawl-20260101120000-00001.warc.gz, ...
+    fn new_file(dir: &Path, prefix: &str, serial: usize) -> IOResult<(String, File, u64)> {
+        let file_name = format!(
+            "{}-{}-{:05}.warc.gz",
+            prefix,
+            Utc::now().format("%Y%m%d%H%M%S"),
+            serial
+        );
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(&file_name))?;
+        // CDX offsets stay right even if a run appends to a file of the same name
+        let written = file.metadata()?.len();
+        Ok((file_name, file, written))
+    }
+
+    fn write_warcinfo(&mut self) -> IOResult<()> {
+        let info = format!(
+            "software: {}/{}\r\nformat: WARC File Format 1.1\r\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        );
+        let record = WarcRecord {
+            warc_type: "warcinfo",
+            record_id: &record_id(),
+            date: &Utc::now(),
+            target_uri: None,
+            content_type: "application/warc-fields",
+            extra_headers: vec![("WARC-Filename", self.file_name.clone())],
+            block: info.as_bytes(),
+        };
+        self.append(&record.to_gzip()?)?;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> IOResult<()> {
+        self.file.flush()?;
+        self.serial += 1;
+        let (file_name, file, written) = Self::new_file(&self.dir, &self.prefix, self.serial)?;
+        self.file_name = file_name;
+        self.file = file;
+        self.written = written;
+        self.fresh = true;
+        self.write_warcinfo()
+    }
+
+    // Returns the offset of the written member in the current file
+    fn append(&mut self, member: &[u8]) -> IOResult<u64> {
+        let offset = self.written;
+        self.file.write_all(member)?;
+        self.written += member.len() as u64;
+        Ok(offset)
+    }
+
+    pub fn write_exchange(
+        &mut self,
+        request: &ExchangeRequest,
+        response: &FetchedResponse,
+    ) -> IOResult<()> {
+        // A file gets at least one exchange, however small `max_file_bytes` is
+        if self.written >= self.max_file_bytes && !self.fresh {
+            self.rotate()?;
+        }
+
+        let date = Utc::now();
+        let response_id = record_id();
+        let payload_digest = format!("sha256:{}", sha256_hex(&response.body));
+        let response_gzip = WarcRecord {
+            warc_type: "response",
+            record_id: &response_id,
+            date: &date,
+            target_uri: Some(&response.url),
+            content_type: "application/http;msgtype=response",
+            extra_headers: vec![("WARC-Payload-Digest", payload_digest.clone())],
+            block: &response_block(response),
+        }
+        .to_gzip()?;
+        let request_gzip = WarcRecord {
+            warc_type: "request",
+            record_id: &record_id(),
+            date: &date,
+            target_uri: Some(&request.url),
+            content_type: "application/http;msgtype=request",
+            extra_headers: vec![("WARC-Concurrent-To", response_id.clone())],
+            block: &request_block(request),
+        }
+        .to_gzip()?;
+
+        let offset = self.append(&response_gzip)?;
+        self.append(&request_gzip)?;
+        self.fresh = false;
+
+        if let Some(cdx) = self.cdx.as_mut() {
+            let mime_type = response
+                .headers
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(';').next())
+                .unwrap_or("-")
+                .trim();
+            let redirect = response
+                .headers
+                .get(LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("-");
+            writeln!(
+                cdx,
+                "{} {} {} {} {} {} {} - {} {} {}",
+                surt(&response.url),
+                date.format("%Y%m%d%H%M%S"),
+                response.url,
+                mime_type,
+                response.status.as_u16(),
+                payload_digest,
+                redirect,
+                response_gzip.len(),
+                offset,
+                self.file_name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl WarcCapture {
+    // Compressing and writing block, so they run on the blocking pool
+    pub async fn record(&self, request: &ExchangeRequest, response: &FetchedResponse) {
+        let (capture, request, response) = (self.clone(), request.clone(), response.clone());
+        let url = response.url.clone();
+        if let Err(e) = tokio::task::spawn_blocking(move || capture.write(&request, &response)).await {
+            error!(url = url.as_str(); "Failed to write {} to WARC: {}", url, e);
+        }
+    }
+
+    fn write(&self, request: &ExchangeRequest, response: &FetchedResponse) {
+        let mut writer_guard = self.writer.lock().expect("WARC writer lock is poisoned");
+        let writer = match writer_guard.as_mut() {
+            Some(writer) => writer,
+            None => match WarcWriter::open(self) {
+                Ok(writer) => writer_guard.insert(writer),
+                Err(e) => {
+                    error!("Failed to open WARC file in {:?}: {}", self.dir, e);
+                    return;
+                }
+            },
+        };
+        if let Err(e) = writer.write_exchange(request, response) {
+            error!(url = response.url.as_str(); "Failed to write {} to WARC: {}", response.url, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, read_dir, read_to_string, remove_dir_all};
+    use std::io::Read;
+
+    use bytes::Bytes;
+    use flate2::read::GzDecoder;
+    use reqwest::header::HeaderValue;
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::replay::{ReplayArchive, ReplaySource};
+
+    fn exchange(url: &str) -> (ExchangeRequest, FetchedResponse) {
+        let url = Url::parse(url).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+        let request = ExchangeRequest {
+            method: Method::GET,
+            url: url.clone(),
+            headers: HeaderMap::new(),
+        };
+        let response = FetchedResponse {
+            url,
+            status: StatusCode::OK,
+            version: Version::HTTP_11,
+            headers,
+            body: Bytes::from_static(b"<p>hello</p>"),
+        };
+        (request, response)
+    }
+
+    fn capture(max_file_bytes: u64) -> (PathBuf, WarcCapture) {
+        let dir = std::env::temp_dir().join(format!("crawler-warc-{}", nanoid::nanoid!(8)));
+        let capture = serde_json::from_value(json!({"dir": dir, "max_file_bytes": max_file_bytes})).unwrap();
+        (dir, capture)
+    }
+
+    #[test]
+    fn surt_keys_reverse_the_host() {
+        let surt_of = |url: &str| surt(&Url::parse(url).unwrap());
+        assert_eq!(surt_of("http://www.Example.com/A?b=1"), "com,example)/a?b=1");
+        assert_eq!(surt_of("https://shop.example.co.uk/"), "uk,co,example,shop)/");
+        assert_eq!(surt_of("http://127.0.0.1/x"), "127.0.0.1)/x");
+    }
+
+    #[test]
+    fn surt_keys_keep_non_default_ports() {
+        let surt_of = |url: &str| surt(&Url::parse(url).unwrap());
+        assert_eq!(surt_of("http://www.example.com:8080/a"), "com,example:8080)/a");
+        assert_eq!(surt_of("http://127.0.0.1:8080/x"), "127.0.0.1:8080)/x");
+        assert_eq!(surt_of("https://example.com:443/"), "com,example)/");
+        assert_eq!(surt_of("http://example.com:80/"), "com,example)/");
+        assert_ne!(surt_of("http://example.com:8080/"), surt_of("http://example.com:8081/"));
+    }
+
+    #[test]
+    fn cdx_lines_point_at_the_response_member() {
+        let (dir, capture) = capture(default_max_file_bytes());
+        let (request, response) = exchange("https://www.example.com/page?id=7");
+        capture.write(&request, &response);
+
+        let cdx = read_to_string(dir.join("crawl.cdx")).unwrap();
+        let mut lines = cdx.lines();
+        assert_eq!(lines.next(), Some(CDX_HEADER.trim_end()));
+        let fields = lines.next().unwrap().split(' ').collect::<Vec<_>>();
+        assert_eq!(fields[0], "com,example)/page?id=7");
+        assert_eq!(&fields[2..5], &["https://www.example.com/page?id=7", "text/html", "200"]);
+        assert_eq!(fields[5], format!("sha256:{}", sha256_hex(b"<p>hello</p>")));
+
+        let (length, offset) = (fields[8].parse::<usize>().unwrap(), fields[9].parse::<usize>().unwrap());
+        let warc = read(dir.join(fields[10])).unwrap();
+        let mut record = String::new();
+        GzDecoder::new(&warc[offset..offset + length]).read_to_string(&mut record).unwrap();
+        assert!(record.starts_with("WARC/1.1\r\nWARC-Type: response\r\n"));
+        assert!(record.ends_with("<p>hello</p>\r\n\r\n"));
+
+        // The replay reader finds the same record
+        let archive = ReplayArchive::open(&ReplaySource::Warc(dir.clone())).unwrap();
+        let replayed = archive.lookup(&response.url).unwrap().unwrap();
+        assert_eq!(replayed.body, response.body);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn full_files_are_rotated() {
+        let (dir, capture) = capture(1);
+        for page in 0..2 {
+            let (request, response) = exchange(&format!("https://example.com/{}", page));
+            capture.write(&request, &response);
+        }
+        let mut names = read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".warc.gz"))
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names.len(), 2);
+        assert!(names[0].ends_with("-00000.warc.gz") && names[1].ends_with("-00001.warc.gz"));
+        remove_dir_all(&dir).unwrap();
+    }
+}
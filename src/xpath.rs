@@ -0,0 +1,114 @@
+use ego_tree::NodeRef;
+use scraper::{Html, Node};
+use serde::{de, Deserialize, Deserializer};
+use sxd_document::dom::{Document, Element};
+use sxd_document::Package;
+use sxd_xpath::{Context, Factory, Value};
+
+use crate::errors::ProcessorError;
+
+// Compiled sxd XPath isn't Send, so only the validated expression is kept in the config
+#[derive(Debug, Clone)]
+pub struct XPathExpr(String);
+
+pub fn de_xpath<'de, D>(deserializer: D) -> Result<XPathExpr, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw_xpath_string: String = Deserialize::deserialize(deserializer)?;
+    match Factory::new().build(&raw_xpath_string) {
+        Ok(Some(_)) => Ok(XPathExpr(raw_xpath_string)),
+        _ => Err(de::Error::invalid_value(
+            de::Unexpected::Str(&format!("Invalid XPath expression {:?}.", raw_xpath_string)),
+            &r#""XPath 1.0 expression.""#,
+        )),
+    }
+}
+
+fn copy_children(document: Document, parent: Element, node: NodeRef<Node>) {
+    for child in node.children() {
+        match child.value() {
+            Node::Element(element) => {
+                let new_element = document.create_element(element.name());
+                element.attrs().for_each(|(name, value)| {
+                    new_element.set_attribute_value(name, value);
+                });
+                parent.append_child(new_element);
+                copy_children(document, new_element, child);
+            }
+            Node::Text(text) => parent.append_child(document.create_text(text)),
+            _ => {}
+        }
+    }
+}
+
+fn html_to_package(html: &str) -> Package {
+    let document_tree = Html::parse_document(html);
+    let package = Package::new();
+    {
+        let document = package.as_document();
+        let root_element = document_tree.root_element();
+        let new_root = document.create_element(root_element.value().name());
+        root_element.value().attrs().for_each(|(name, value)| {
+            new_root.set_attribute_value(name, value);
+        });
+        document.root().append_child(new_root);
+        copy_children(document, new_root, *root_element);
+    }
+    package
+}
+
+pub fn select_strings(html: &str, xpath: &XPathExpr) -> Result<Vec<String>, ProcessorError> {
+    let compiled = Factory::new()
+        .build(&xpath.0)
+        .map_err(|e| ProcessorError::XPathError(e.to_string()))?
+        .ok_or_else(|| ProcessorError::XPathError(format!("Empty XPath {:?}", xpath.0)))?;
+
+    let package = html_to_package(html);
+    let document = package.as_document();
+    let value = compiled
+        .evaluate(&Context::new(), document.root())
+        .map_err(|e| ProcessorError::XPathError(e.to_string()))?;
+
+    Ok(match value {
+        Value::Nodeset(nodes) => nodes
+            .document_order()
+            .iter()
+            .map(|node| node.string_value())
+            .collect(),
+        Value::Boolean(_) | Value::Number(_) | Value::String(_) => vec![value.into_string()],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const PAGE: &str = r#"<html><body>
+        <ul><li class="item"><a href="/a">First</a></li><li class="item"><a href="/b">Second <b>bold</b></a></li></ul>
+        <p>Count me</p>
+    </body></html>"#;
+
+    fn xpath(raw: &str) -> XPathExpr {
+        de_xpath(json!(raw)).unwrap()
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected_on_load() {
+        assert!(de_xpath(json!("//li[")).is_err());
+    }
+
+    #[test]
+    fn node_sets_give_attributes_and_text_in_document_order() {
+        assert_eq!(select_strings(PAGE, &xpath("//li[@class='item']/a/@href")).unwrap(), vec!["/a", "/b"]);
+        assert_eq!(select_strings(PAGE, &xpath("//a")).unwrap(), vec!["First", "Second bold"]);
+    }
+
+    #[test]
+    fn scalar_results_give_one_string() {
+        assert_eq!(select_strings(PAGE, &xpath("count(//li)")).unwrap(), vec!["2"]);
+        assert_eq!(select_strings(PAGE, &xpath("normalize-space(//p)")).unwrap(), vec!["Count me"]);
+    }
+}